@@ -0,0 +1,367 @@
+use std::{
+    f32::consts::TAU,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail};
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    FromSample, SampleFormat, SizedSample, StreamConfig,
+};
+
+use super::{AudioConfig, Capture, Feed};
+
+/// How often the generator thread hands out audio when not playing to a device
+const BLOCK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Test signals the generator source produces
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Signal {
+    Sine,
+    White,
+    Pink,
+    /// Logarithmic sweep, repeating
+    Sweep,
+    /// Sine switched on and off, for checking ballistics
+    Burst,
+}
+
+impl FromStr for Signal {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sine" => Ok(Signal::Sine),
+            "white" => Ok(Signal::White),
+            "pink" => Ok(Signal::Pink),
+            "sweep" => Ok(Signal::Sweep),
+            "burst" => Ok(Signal::Burst),
+            _ => Err(anyhow!("unknown signal '{s}'")),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GeneratorConfig {
+    pub signal: Signal,
+    /// Sine and burst frequency in Hz
+    pub frequency: f32,
+    /// Level in dBFS, 0 being a full scale sine. Noise has the RMS of a sine at this level.
+    pub level: f32,
+    /// Sweep start and end frequencies in Hz
+    pub sweep: (f32, f32),
+    /// Seconds per sweep
+    pub sweep_time: f32,
+    /// Seconds a burst is on, and seconds from one burst to the next
+    pub burst: (f32, f32),
+    /// Play the signal to an output device as well
+    pub play: bool,
+    /// Output device name, the default output if unset
+    pub output: Option<String>,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            signal: Signal::Sine,
+            frequency: 1000.0,
+            level: -18.0,
+            sweep: (20.0, 20000.0),
+            sweep_time: 10.0,
+            burst: (0.3, 2.0),
+            play: false,
+            output: None,
+        }
+    }
+}
+
+/// Produces the configured signal, the same on every channel
+struct Generator {
+    config: GeneratorConfig,
+    samplerate: f32,
+    amplitude: f32,
+    /// Samples generated so far
+    position: u64,
+    phase: f32,
+    rng: u32,
+    pink: [f32; 7],
+    pink_gain: f32,
+}
+
+/// Paul Kellet's pink noise filter, a sum of one-pole lowpasses as (pole, gain)
+const PINK_POLES: [(f32, f32); 6] = [
+    (0.99886, 0.0555179),
+    (0.99332, 0.0750759),
+    (0.96900, 0.153852),
+    (0.86650, 0.3104856),
+    (0.55000, 0.5329522),
+    (-0.7616, -0.0168980),
+];
+const PINK_DIRECT: f32 = 0.5362;
+const PINK_DELAYED: f32 = 0.115926;
+
+impl Generator {
+    fn new(config: GeneratorConfig, samplerate: usize) -> Result<Self, anyhow::Error> {
+        let nyquist = samplerate as f32 / 2.0;
+        let highest = match config.signal {
+            Signal::Sine | Signal::Burst => config.frequency,
+            Signal::Sweep => config.sweep.0.max(config.sweep.1),
+            Signal::White | Signal::Pink => 0.0,
+        };
+        if highest >= nyquist {
+            bail!("{highest}Hz is above the {nyquist}Hz Nyquist frequency");
+        }
+
+        Ok(Self {
+            amplitude: 10f32.powf(config.level / 20.0),
+            config,
+            samplerate: samplerate as f32,
+            position: 0,
+            phase: 0.0,
+            rng: 0x2545_f491,
+            pink: [0.0; 7],
+            pink_gain: pink_gain(),
+        })
+    }
+
+    /// Uniform noise with unit variance
+    fn white(&mut self) -> f32 {
+        // xorshift32
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        let uniform = self.rng as f32 / u32::MAX as f32 * 2.0 - 1.0;
+        uniform * 3f32.sqrt()
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        // f64 keeps burst timing sample accurate over long runs
+        let time = self.position as f64 / self.samplerate as f64;
+        self.position += 1;
+
+        let frequency = match self.config.signal {
+            Signal::Sine => self.config.frequency,
+            Signal::Sweep => {
+                let (from, to) = self.config.sweep;
+                let sweep_time = self.config.sweep_time as f64;
+                let progress = (time % sweep_time / sweep_time) as f32;
+                from * (to / from).powf(progress)
+            }
+            Signal::Burst => {
+                let (on, period) = self.config.burst;
+                if time % period as f64 >= on as f64 {
+                    // Bursts start at a zero crossing
+                    self.phase = 0.0;
+                    return 0.0;
+                }
+                self.config.frequency
+            }
+            Signal::White => return self.white() * self.amplitude / 2f32.sqrt(),
+            Signal::Pink => {
+                let white = self.white();
+                let mut pink = self.pink[6] + white * PINK_DIRECT;
+                for (state, (pole, gain)) in self.pink.iter_mut().zip(PINK_POLES) {
+                    *state = pole * *state + white * gain;
+                    pink += *state;
+                }
+                self.pink[6] = white * PINK_DELAYED;
+                return pink * self.pink_gain * self.amplitude / 2f32.sqrt();
+            }
+        };
+
+        let sample = self.phase.sin() * self.amplitude;
+        self.phase = (self.phase + TAU * frequency / self.samplerate) % TAU;
+        sample
+    }
+
+    fn fill(&mut self, frames: usize, channels: usize) -> Vec<f32> {
+        let mut data = Vec::with_capacity(frames * channels);
+        for _ in 0..frames {
+            let sample = self.next_sample();
+            data.extend(std::iter::repeat_n(sample, channels));
+        }
+        data
+    }
+}
+
+/// Scales the pink filter to unit variance for unit variance white noise, from the
+/// energy of its impulse response
+fn pink_gain() -> f32 {
+    let energy: f32 = (0..20000)
+        .map(|k| {
+            let poles: f32 = PINK_POLES
+                .iter()
+                .map(|(pole, gain)| gain * pole.powi(k))
+                .sum();
+            let direct = match k {
+                0 => PINK_DIRECT,
+                1 => PINK_DELAYED,
+                _ => 0.0,
+            };
+            (poles + direct).powi(2)
+        })
+        .sum();
+    1.0 / energy.sqrt()
+}
+
+/// Generator thread, stopped and joined when dropped
+struct GeneratorCapture {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for GeneratorCapture {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Starts the test signal generator. When playing to an output device the device
+/// clocks it, otherwise a thread hands it out in real time.
+pub fn open_generator(
+    feed: &Feed,
+    audio_config: &AudioConfig,
+) -> Result<(Capture, usize, usize), anyhow::Error> {
+    let config = &audio_config.generator;
+    if config.play {
+        return open_playback(feed, config);
+    }
+
+    let samplerate = audio_config.samplerate.unwrap_or(48000) as usize;
+    let channels = audio_config.channels.unwrap_or(2);
+    let mut generator = Generator::new(config.clone(), samplerate)?;
+    println!(
+        "Generating {:?} at {}dBFS, {} ch, {}Hz",
+        config.signal, config.level, channels, samplerate
+    );
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let (feed, stop) = (feed.clone(), stop.clone());
+        std::thread::spawn(move || {
+            let started = Instant::now();
+            let mut generated = 0;
+            while !stop.load(Ordering::Relaxed) {
+                std::thread::sleep(BLOCK_INTERVAL);
+                let due = (started.elapsed().as_secs_f64() * samplerate as f64) as usize;
+                feed.send(generator.fill(due - generated, channels));
+                generated = due;
+            }
+        })
+    };
+
+    let capture = GeneratorCapture {
+        stop,
+        thread: Some(thread),
+    };
+    Ok((Box::new(capture), samplerate, channels))
+}
+
+fn open_playback(
+    feed: &Feed,
+    config: &GeneratorConfig,
+) -> Result<(Capture, usize, usize), anyhow::Error> {
+    let host = cpal::default_host();
+    let device = match &config.output {
+        Some(name) => host
+            .output_devices()?
+            .find(|device| device.name().is_ok_and(|device_name| &device_name == name))
+            .ok_or_else(|| anyhow!("output device '{name}' not found"))?,
+        None => host
+            .default_output_device()
+            .ok_or_else(|| anyhow!("failed to find output device"))?,
+    };
+
+    let supported = device.default_output_config()?;
+    let samplerate = supported.sample_rate().0 as usize;
+    let channels = supported.channels() as usize;
+    let generator = Generator::new(config.clone(), samplerate)?;
+    println!(
+        "Playing {:?} at {}dBFS to {}, {} ch, {}Hz",
+        config.signal,
+        config.level,
+        device.name()?,
+        channels,
+        samplerate
+    );
+
+    let stream_config = supported.config();
+    let stream = match supported.sample_format() {
+        SampleFormat::I8 => build_output::<i8>(&device, &stream_config, generator, feed),
+        SampleFormat::I16 => build_output::<i16>(&device, &stream_config, generator, feed),
+        SampleFormat::I32 => build_output::<i32>(&device, &stream_config, generator, feed),
+        SampleFormat::I64 => build_output::<i64>(&device, &stream_config, generator, feed),
+        SampleFormat::U8 => build_output::<u8>(&device, &stream_config, generator, feed),
+        SampleFormat::U16 => build_output::<u16>(&device, &stream_config, generator, feed),
+        SampleFormat::U32 => build_output::<u32>(&device, &stream_config, generator, feed),
+        SampleFormat::U64 => build_output::<u64>(&device, &stream_config, generator, feed),
+        SampleFormat::F32 => build_output::<f32>(&device, &stream_config, generator, feed),
+        SampleFormat::F64 => build_output::<f64>(&device, &stream_config, generator, feed),
+        sample_format => bail!("Unsupported sample format '{sample_format}'"),
+    }?;
+    stream.play()?;
+
+    Ok((Box::new(stream), samplerate, channels))
+}
+
+/// Plays the generator and meters exactly what was played
+fn build_output<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    mut generator: Generator,
+    feed: &Feed,
+) -> Result<cpal::Stream, anyhow::Error>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let (feed, err_feed) = (feed.clone(), feed.clone());
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &_| {
+            let samples = generator.fill(data.len() / channels, channels);
+            for (out, sample) in data.iter_mut().zip(&samples) {
+                *out = T::from_sample(*sample);
+            }
+            feed.send(samples);
+        },
+        move |err| err_feed.fail(err),
+        None,
+    )?;
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|x| x * x).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn signals_have_the_rms_of_a_sine_at_the_level() {
+        let expected = 10f32.powf(-18.0 / 20.0) / 2f32.sqrt();
+        for signal in [Signal::Sine, Signal::White, Signal::Pink] {
+            let config = GeneratorConfig {
+                signal,
+                ..Default::default()
+            };
+            let mut generator = Generator::new(config, 48000).unwrap();
+            let level = rms(&generator.fill(480000, 1));
+            assert!(
+                (20.0 * (level / expected).log10()).abs() < 0.2,
+                "{signal:?} at {level}, expected {expected}"
+            );
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use jack::{AudioIn, Client, ClientOptions, ClosureProcessHandler, Control, ProcessScope};
+
+use super::{AudioConfig, Capture, Feed};
+
+/// Starts a JACK client with one named input port per channel.
+/// Under PipeWire this shows up in the graph through its JACK implementation.
+pub fn open_jack(
+    feed: &Feed,
+    config: &AudioConfig,
+) -> Result<(Capture, usize, usize), anyhow::Error> {
+    let (client, _status) = Client::new(&config.jack_name, ClientOptions::NO_START_SERVER)?;
+    let ports = config
+        .jack_ports
+        .iter()
+        .map(|name| client.register_port(name, AudioIn))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    println!(
+        "JACK client: {} with ports {:?}",
+        client.name(),
+        config.jack_ports
+    );
+
+    let samplerate = client.sample_rate();
+    let channels = ports.len();
+
+    let feed = feed.clone();
+    let process = ClosureProcessHandler::new(move |_: &Client, ps: &ProcessScope| {
+        let inputs: Vec<&[f32]> = ports.iter().map(|port| port.as_slice(ps)).collect();
+        let frames = inputs.first().map_or(0, |input| input.len());
+
+        let mut data = Vec::with_capacity(frames * inputs.len());
+        for frame in 0..frames {
+            data.extend(inputs.iter().map(|input| input[frame]));
+        }
+
+        feed.send(data);
+        Control::Continue
+    });
+
+    // Deactivated when dropped, a stopped server shows up as a stall
+    let active = client.activate_async((), process)?;
+
+    Ok((Box::new(active), samplerate, channels))
+}
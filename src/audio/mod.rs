@@ -0,0 +1,613 @@
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{
+    BufferSize, BuildStreamError, FromSample, Sample, SampleFormat, SampleRate, SizedSample,
+    StreamConfig, StreamError, SupportedBufferSize,
+};
+use std::any::Any;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+
+use crate::AudioEvent;
+
+mod generator;
+#[cfg(feature = "jack")]
+mod jack_input;
+mod network;
+mod pipe;
+
+pub use generator::GeneratorConfig;
+pub use network::NetFormat;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// A capture delivering nothing for this long counts as lost
+const STALL_TIMEOUT: Duration = Duration::from_secs(2);
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Backend the meter captures from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioHost {
+    /// cpal's default host, ALSA on Linux
+    Default,
+    /// Native JACK client with named ports, needs the `jack` feature
+    Jack,
+    /// PipeWire through its ALSA plugin device
+    PipeWire,
+}
+
+impl FromStr for AudioHost {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" | "alsa" => Ok(AudioHost::Default),
+            "jack" => Ok(AudioHost::Jack),
+            "pipewire" => Ok(AudioHost::PipeWire),
+            _ => Err(anyhow!("unknown audio host '{s}'")),
+        }
+    }
+}
+
+/// What gets metered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// An input device such as a microphone or line in
+    Device,
+    /// What the system is playing, through a monitor or loopback device
+    Monitor,
+    /// L16/L24 over RTP, as AES67 sends it
+    Rtp,
+    /// Bare L16/L24 datagrams over UDP, without loss detection
+    Udp,
+    /// Raw PCM piped into standard input
+    Stdin,
+    /// Raw PCM from a named pipe
+    Pipe,
+    /// Built-in test signal generator
+    Generator,
+}
+
+impl FromStr for Source {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "device" => Ok(Source::Device),
+            "monitor" => Ok(Source::Monitor),
+            "rtp" => Ok(Source::Rtp),
+            "udp" => Ok(Source::Udp),
+            "stdin" => Ok(Source::Stdin),
+            "pipe" => Ok(Source::Pipe),
+            "generator" => Ok(Source::Generator),
+            _ => Err(anyhow!("unknown source '{s}'")),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AudioConfig {
+    pub source: Source,
+    pub host: AudioHost,
+    /// Input device name, the host default if unset
+    pub device: Option<String>,
+    /// Name the JACK client registers with
+    pub jack_name: String,
+    /// JACK input ports, one per captured channel
+    pub jack_ports: Vec<String>,
+    /// Requested capture settings, the device defaults where unset or unsupported
+    pub samplerate: Option<u32>,
+    /// Frames per buffer
+    pub buffer_size: Option<u32>,
+    pub sample_format: Option<SampleFormat>,
+    /// Byte order of raw PCM input
+    pub big_endian: bool,
+    /// Channels of sources that can't tell, 2 if unset
+    pub channels: Option<usize>,
+    /// Address network sources receive on, multicast groups are joined
+    pub listen: SocketAddr,
+    pub net_format: NetFormat,
+    /// Jitter buffer length for RTP in seconds
+    pub jitter: f32,
+    /// Named pipe raw PCM is read from
+    pub pipe: Option<String>,
+    pub generator: GeneratorConfig,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            source: Source::Device,
+            host: AudioHost::Default,
+            device: None,
+            jack_name: "vu".into(),
+            jack_ports: vec!["L".into(), "R".into()],
+            samplerate: None,
+            buffer_size: None,
+            sample_format: None,
+            big_endian: false,
+            channels: None,
+            listen: SocketAddr::from(([0, 0, 0, 0], 5004)),
+            net_format: NetFormat::L24,
+            jitter: 0.02,
+            pipe: None,
+            generator: GeneratorConfig::default(),
+        }
+    }
+}
+
+/// Keeps a running capture alive until dropped
+type Capture = Box<dyn Any>;
+
+/// The input is over for good and isn't reopened, like stdin at its end
+#[derive(Debug)]
+pub struct EndOfInput;
+
+impl Display for EndOfInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "end of input")
+    }
+}
+
+impl std::error::Error for EndOfInput {}
+
+/// Hands captured buffers to the UI and lets the supervisor see how the capture is doing
+#[derive(Clone)]
+struct Feed {
+    tx: Sender<AudioEvent>,
+    buffers: Arc<AtomicUsize>,
+    errors: Sender<String>,
+}
+
+impl Feed {
+    fn send(&self, buf: Vec<f32>) {
+        self.buffers.fetch_add(1, Ordering::Relaxed);
+        // The UI going away is handled by the supervisor
+        let _ = self.tx.send(AudioEvent::Buffer { buf });
+    }
+
+    fn event(&self, event: AudioEvent) {
+        let _ = self.tx.send(event);
+    }
+
+    /// Reports an error the capture cannot recover from
+    fn fail(&self, err: impl Display) {
+        let _ = self.errors.send(err.to_string());
+    }
+}
+
+/// Supervises capture until shutdown: opens the device, watches it for errors and
+/// stalls, and keeps reopening it while it is gone
+pub fn audio_thread(tx: Sender<AudioEvent>, shutdown_rx: Receiver<()>, audio_config: AudioConfig) {
+    supervise(tx, shutdown_rx, audio_config, open_capture);
+}
+
+fn supervise(
+    tx: Sender<AudioEvent>,
+    shutdown_rx: Receiver<()>,
+    audio_config: AudioConfig,
+    open: impl Fn(&Feed, &AudioConfig) -> Result<(Capture, usize, usize), anyhow::Error>,
+) {
+    let (mut samplerate, mut channels) = (44100, 2);
+    // Why the input is gone, so retries failing the same way aren't logged again
+    let mut lost: Option<String> = None;
+
+    loop {
+        let (errors_tx, errors_rx) = mpsc::channel();
+        let feed = Feed {
+            tx: tx.clone(),
+            buffers: Default::default(),
+            errors: errors_tx,
+        };
+
+        let (reason, ended) = match open(&feed, &audio_config) {
+            Ok((capture, opened_samplerate, opened_channels)) => {
+                if lost.take().is_some() {
+                    println!("audio input back");
+                }
+                (samplerate, channels) = (opened_samplerate, opened_channels);
+                let config = AudioEvent::Config {
+                    samplerate,
+                    channels,
+                    positioned: matches!(audio_config.source, Source::Stdin | Source::Pipe),
+                };
+                if tx.send(config).is_err() {
+                    return;
+                }
+                let Some(reason) = watch(&feed, &errors_rx, &shutdown_rx) else {
+                    return;
+                };
+                drop(capture);
+                (reason, false)
+            }
+            Err(err) => (err.to_string(), err.is::<EndOfInput>()),
+        };
+
+        if lost.as_ref() != Some(&reason) {
+            eprintln!("audio input lost: {reason}");
+            lost = Some(reason.clone());
+        }
+        if tx.send(AudioEvent::Lost { reason }).is_err() {
+            return;
+        }
+
+        // Meter silence while waiting, so needles fall back instead of freezing.
+        // Input that has ended is never retried, only shutdown ends the wait.
+        let retry_at = Instant::now() + RETRY_INTERVAL;
+        while ended || Instant::now() < retry_at {
+            match shutdown_rx.recv_timeout(POLL_INTERVAL) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => return,
+            }
+            let frames = (samplerate as f32 * POLL_INTERVAL.as_secs_f32()) as usize;
+            let buf = vec![0.0; frames * channels];
+            if tx.send(AudioEvent::Silence { buf }).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Waits until shutdown, returning None, or until the capture fails or stalls,
+/// returning why
+fn watch(feed: &Feed, errors_rx: &Receiver<String>, shutdown_rx: &Receiver<()>) -> Option<String> {
+    let mut buffers = 0;
+    let mut last_progress = Instant::now();
+
+    loop {
+        match shutdown_rx.recv_timeout(POLL_INTERVAL) {
+            Err(RecvTimeoutError::Timeout) => {}
+            _ => return None,
+        }
+        if let Ok(err) = errors_rx.try_recv() {
+            return Some(err);
+        }
+
+        let latest = feed.buffers.load(Ordering::Relaxed);
+        if latest != buffers {
+            buffers = latest;
+            last_progress = Instant::now();
+        } else if last_progress.elapsed() > STALL_TIMEOUT {
+            return Some("no audio from device".into());
+        }
+    }
+}
+
+/// Starts capturing, returns the capture with its sample rate and channel count
+fn open_capture(
+    feed: &Feed,
+    audio_config: &AudioConfig,
+) -> Result<(Capture, usize, usize), anyhow::Error> {
+    if let Source::Rtp | Source::Udp = audio_config.source {
+        return network::open_network(feed, audio_config);
+    }
+    if let Source::Stdin | Source::Pipe = audio_config.source {
+        return pipe::open_pipe(feed, audio_config);
+    }
+    if audio_config.source == Source::Generator {
+        return generator::open_generator(feed, audio_config);
+    }
+
+    let device_name = match audio_config.host {
+        AudioHost::Default => audio_config.device.as_deref(),
+        AudioHost::PipeWire => Some(audio_config.device.as_deref().unwrap_or("pipewire")),
+        #[cfg(feature = "jack")]
+        AudioHost::Jack => return jack_input::open_jack(feed, audio_config),
+        #[cfg(not(feature = "jack"))]
+        AudioHost::Jack => return Err(anyhow!("built without JACK, enable the 'jack' feature")),
+    };
+
+    let host = cpal::default_host();
+
+    let device = match (device_name, audio_config.source) {
+        (Some(name), _) => host
+            .input_devices()?
+            .find(|device| device.name().is_ok_and(|device_name| device_name == name))
+            .ok_or_else(|| anyhow!("input device '{name}' not found"))?,
+        (None, Source::Monitor) => find_monitor_device(&host)?,
+        (None, _) => host
+            .default_input_device()
+            .ok_or_else(|| anyhow!("failed to find input device"))?,
+    };
+
+    println!("Input device: {}", device.name()?);
+
+    let (config, sample_format) = choose_config(&device, audio_config)?;
+    println!("Input config: {:?} {}", config, sample_format);
+
+    let samplerate = config.sample_rate.0 as usize;
+    let channels = config.channels as usize;
+
+    let err_feed = feed.clone();
+    let err_fn = move |err| {
+        eprintln!("an error occurred on stream: {}", err);
+        if let StreamError::DeviceNotAvailable = err {
+            err_feed.fail(err);
+        }
+    };
+
+    let stream = match build_stream(&device, &config, sample_format, feed, err_fn.clone()) {
+        Err(err) if config.buffer_size != BufferSize::Default => {
+            eprintln!(
+                "buffer size {:?} rejected ({err}), using default",
+                config.buffer_size
+            );
+            let config = StreamConfig {
+                buffer_size: BufferSize::Default,
+                ..config
+            };
+            build_stream(&device, &config, sample_format, feed, err_fn)?
+        }
+        stream => stream?,
+    };
+
+    stream.play()?;
+
+    Ok((Box::new(stream), samplerate, channels))
+}
+
+/// Loopback and monitor devices capture what is being played
+fn is_monitor(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.contains("monitor") || name.contains("loopback")
+}
+
+fn find_monitor_device(host: &cpal::Host) -> Result<cpal::Device, anyhow::Error> {
+    let devices: Vec<_> = host.input_devices()?.collect();
+    let named = |matches: &dyn Fn(&str) -> bool| {
+        devices
+            .iter()
+            .find(|device| device.name().is_ok_and(|name| matches(&name)))
+            .cloned()
+    };
+    // Otherwise a sound server device, pointed at its output by set_monitor_env
+    named(&is_monitor)
+        .or_else(|| named(&|name| name == "pipewire" || name == "pulse"))
+        .ok_or_else(|| anyhow!("no monitor or loopback device found, see --list-devices"))
+}
+
+/// Points PulseAudio and PipeWire capture streams at the default output instead of
+/// the default input. Environment is process wide, so call before spawning threads.
+pub fn set_monitor_env(audio_config: &AudioConfig) {
+    if audio_config.source != Source::Monitor {
+        return;
+    }
+    if std::env::var_os("PULSE_SOURCE").is_none() {
+        std::env::set_var("PULSE_SOURCE", "@DEFAULT_MONITOR@");
+    }
+    if std::env::var_os("PIPEWIRE_PROPS").is_none() {
+        std::env::set_var("PIPEWIRE_PROPS", "{ stream.capture.sink = true }");
+    }
+}
+
+/// Prints input devices of every available host, marking monitor and loopback ones
+pub fn list_devices() -> Result<(), anyhow::Error> {
+    for host_id in cpal::available_hosts() {
+        println!("{}:", host_id.name());
+        // One broken host shouldn't hide the devices of the others
+        let devices = cpal::host_from_id(host_id)
+            .map_err(anyhow::Error::from)
+            .and_then(|host| Ok(host.input_devices()?));
+        let devices = match devices {
+            Ok(devices) => devices,
+            Err(err) => {
+                eprintln!("  cannot list devices: {err}");
+                continue;
+            }
+        };
+        for device in devices {
+            let Ok(name) = device.name() else {
+                continue;
+            };
+            let mark = if is_monitor(&name) { "  (monitor)" } else { "" };
+            match device.default_input_config() {
+                Ok(config) => println!(
+                    "  {name}{mark}  {} ch, {}Hz, {}",
+                    config.channels(),
+                    config.sample_rate().0,
+                    config.sample_format()
+                ),
+                Err(_) => println!("  {name}{mark}"),
+            }
+        }
+    }
+    println!("Meter playback with --source monitor, optionally with --device <name>");
+    Ok(())
+}
+
+/// Picks the requested sample rate and format where the device supports them,
+/// otherwise falls back towards the default config one setting at a time
+fn choose_config(
+    device: &cpal::Device,
+    audio_config: &AudioConfig,
+) -> Result<(StreamConfig, SampleFormat), anyhow::Error> {
+    let default = device.default_input_config()?;
+    let rate = audio_config.samplerate.unwrap_or(default.sample_rate().0);
+    let format = audio_config
+        .sample_format
+        .unwrap_or(default.sample_format());
+
+    let ranges: Vec<_> = device.supported_input_configs()?.collect();
+    let candidates = [
+        (format, rate),
+        (default.sample_format(), rate),
+        (format, default.sample_rate().0),
+    ];
+    let supported = candidates
+        .into_iter()
+        .find_map(|(format, rate)| {
+            let matching = ranges.iter().filter(|range| {
+                range.sample_format() == format
+                    && (range.min_sample_rate().0..=range.max_sample_rate().0).contains(&rate)
+            });
+            // Keep the default channel count if the device offers it with this format
+            matching
+                .clone()
+                .find(|range| range.channels() == default.channels())
+                .or(matching.min_by_key(|range| range.channels()))
+                .map(|range| range.with_sample_rate(SampleRate(rate)))
+        })
+        .unwrap_or_else(|| default.clone());
+
+    if supported.sample_rate().0 != rate || supported.sample_format() != format {
+        eprintln!(
+            "{}Hz {} not supported, using {}Hz {}",
+            rate,
+            format,
+            supported.sample_rate().0,
+            supported.sample_format()
+        );
+    }
+
+    let buffer_size = match (audio_config.buffer_size, supported.buffer_size()) {
+        (None, _) => BufferSize::Default,
+        (Some(frames), SupportedBufferSize::Range { min, max }) => {
+            BufferSize::Fixed(frames.clamp(*min, *max))
+        }
+        (Some(frames), SupportedBufferSize::Unknown) => BufferSize::Fixed(frames),
+    };
+
+    let sample_format = supported.sample_format();
+    let config = StreamConfig {
+        buffer_size,
+        ..supported.config()
+    };
+    Ok((config, sample_format))
+}
+
+fn build_stream(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    sample_format: SampleFormat,
+    feed: &Feed,
+    err_fn: impl FnMut(StreamError) + Send + 'static,
+) -> Result<cpal::Stream, anyhow::Error> {
+    let stream = match sample_format {
+        SampleFormat::I8 => build_typed_stream::<i8>(device, config, feed, err_fn),
+        SampleFormat::I16 => build_typed_stream::<i16>(device, config, feed, err_fn),
+        SampleFormat::I32 => build_typed_stream::<i32>(device, config, feed, err_fn),
+        SampleFormat::I64 => build_typed_stream::<i64>(device, config, feed, err_fn),
+        SampleFormat::U8 => build_typed_stream::<u8>(device, config, feed, err_fn),
+        SampleFormat::U16 => build_typed_stream::<u16>(device, config, feed, err_fn),
+        SampleFormat::U32 => build_typed_stream::<u32>(device, config, feed, err_fn),
+        SampleFormat::U64 => build_typed_stream::<u64>(device, config, feed, err_fn),
+        SampleFormat::F32 => build_typed_stream::<f32>(device, config, feed, err_fn),
+        SampleFormat::F64 => build_typed_stream::<f64>(device, config, feed, err_fn),
+        sample_format => {
+            return Err(anyhow::Error::msg(format!(
+                "Unsupported sample format '{sample_format}'"
+            )))
+        }
+    };
+    Ok(stream?)
+}
+
+fn build_typed_stream<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    feed: &Feed,
+    err_fn: impl FnMut(StreamError) + Send + 'static,
+) -> Result<cpal::Stream, BuildStreamError>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let feed = feed.clone();
+    device.build_input_stream(
+        config,
+        move |data, _: &_| write_input_data::<T, T>(data, &feed),
+        err_fn,
+        None,
+    )
+}
+
+/// Parses a sample format name as cpal prints it, e.g. `i16`, `u8` or `f32`
+pub fn parse_sample_format(s: &str) -> Result<SampleFormat, anyhow::Error> {
+    match s {
+        "i8" => Ok(SampleFormat::I8),
+        "i16" => Ok(SampleFormat::I16),
+        "i32" => Ok(SampleFormat::I32),
+        "i64" => Ok(SampleFormat::I64),
+        "u8" => Ok(SampleFormat::U8),
+        "u16" => Ok(SampleFormat::U16),
+        "u32" => Ok(SampleFormat::U32),
+        "u64" => Ok(SampleFormat::U64),
+        "f32" => Ok(SampleFormat::F32),
+        "f64" => Ok(SampleFormat::F64),
+        _ => Err(anyhow!("unknown sample format '{s}'")),
+    }
+}
+
+/// Parses a sample format with an optional byte order suffix as ffmpeg names them,
+/// e.g. `f32le` or `s16be`. Returns the format and whether it is big-endian.
+pub fn parse_raw_format(s: &str) -> Result<(SampleFormat, bool), anyhow::Error> {
+    let (format, big_endian) = match s.strip_suffix("be") {
+        Some(format) => (format, true),
+        None => (s.strip_suffix("le").unwrap_or(s), false),
+    };
+    let format = match format.strip_prefix('s') {
+        Some(bits) => parse_sample_format(&format!("i{bits}")),
+        None => parse_sample_format(format),
+    }
+    .map_err(|_| anyhow!("unknown sample format '{s}'"))?;
+    Ok((format, big_endian))
+}
+
+fn write_input_data<T, U>(input: &[T], feed: &Feed)
+where
+    T: Sample,
+    U: Sample,
+    f32: FromSample<T>
+{
+    let mut data = vec![];
+    for x in input {
+        let s = x.to_sample::<f32>();
+        data.push(s);
+    }
+
+    feed.send(data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supervisor_stops_reopening_at_the_end_of_input() {
+        let (tx, rx) = mpsc::channel();
+        let (shutdown_tx, shutdown_rx) = mpsc::channel();
+        let opens = Arc::new(AtomicUsize::new(0));
+        let supervisor = {
+            let opens = opens.clone();
+            std::thread::spawn(move || {
+                supervise(tx, shutdown_rx, AudioConfig::default(), |feed, _| {
+                    if opens.fetch_add(1, Ordering::Relaxed) > 0 {
+                        return Err(EndOfInput.into());
+                    }
+                    // Input that runs out right away
+                    feed.fail(EndOfInput);
+                    Ok((Box::new(()), 48000, 2))
+                })
+            })
+        };
+
+        std::thread::sleep(RETRY_INTERVAL * 3);
+        assert_eq!(opens.load(Ordering::Relaxed), 2);
+        shutdown_tx.send(()).unwrap();
+        supervisor.join().unwrap();
+
+        let events: Vec<_> = rx.try_iter().collect();
+        let lost = events
+            .iter()
+            .filter(|event| matches!(event, AudioEvent::Lost { .. }))
+            .count();
+        assert_eq!(lost, 2);
+        // Still metering silence after the end, so the needles fall back
+        assert!(matches!(events.last(), Some(AudioEvent::Silence { .. })));
+    }
+}
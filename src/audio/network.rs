@@ -0,0 +1,410 @@
+use std::{
+    collections::VecDeque,
+    io::ErrorKind,
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use anyhow::anyhow;
+
+use crate::AudioEvent;
+
+use super::{AudioConfig, Capture, Feed, Source};
+
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+/// Packets this far ahead of the expected one mean the sender restarted
+const RESYNC_DISTANCE: usize = 1000;
+const RTP_HEADER_LEN: usize = 12;
+
+/// Big-endian PCM payload encodings, as in RTP L16/L24
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NetFormat {
+    L16,
+    L24,
+}
+
+impl NetFormat {
+    fn bytes(&self) -> usize {
+        match self {
+            NetFormat::L16 => 2,
+            NetFormat::L24 => 3,
+        }
+    }
+
+    fn decode(&self, payload: &[u8]) -> Vec<f32> {
+        payload
+            .chunks_exact(self.bytes())
+            .map(|bytes| match self {
+                NetFormat::L16 => i16::from_be_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+                NetFormat::L24 => {
+                    // Sign extend through the top byte of an i32
+                    i32::from_be_bytes([bytes[0], bytes[1], bytes[2], 0]) as f32 / 2147483648.0
+                }
+            })
+            .collect()
+    }
+}
+
+impl FromStr for NetFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "l16" | "L16" => Ok(NetFormat::L16),
+            "l24" | "L24" => Ok(NetFormat::L24),
+            _ => Err(anyhow!("unknown network format '{s}'")),
+        }
+    }
+}
+
+/// Sequence number and payload of an RTP packet, None if it isn't one
+fn parse_rtp(packet: &[u8]) -> Option<(u16, &[u8])> {
+    if packet.len() < RTP_HEADER_LEN || packet[0] >> 6 != 2 {
+        return None;
+    }
+    let csrc_count = (packet[0] & 0x0f) as usize;
+    let mut start = RTP_HEADER_LEN + 4 * csrc_count;
+    if packet[0] & 0x10 != 0 {
+        let words = u16::from_be_bytes([*packet.get(start + 2)?, *packet.get(start + 3)?]);
+        start += 4 + 4 * words as usize;
+    }
+    let padding = if packet[0] & 0x20 != 0 {
+        *packet.last()? as usize
+    } else {
+        0
+    };
+    let end = packet.len().checked_sub(padding)?;
+    let sequence = u16::from_be_bytes([packet[2], packet[3]]);
+    Some((sequence, packet.get(start..end)?))
+}
+
+/// Reorders packets by sequence number, holding back `depth` samples worth of packets
+/// so late ones can still slot in. Packets that never arrive are replaced by silence.
+pub struct JitterBuffer {
+    depth: usize,
+    /// Sequence number of the front slot
+    next: Option<u16>,
+    slots: VecDeque<Option<Vec<f32>>>,
+    /// Samples per packet, for concealing lost ones
+    packet_len: usize,
+    pub lost: u64,
+}
+
+impl JitterBuffer {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            next: None,
+            slots: VecDeque::new(),
+            packet_len: 0,
+            lost: 0,
+        }
+    }
+
+    /// Takes a packet in, returns packets ready to play in order
+    pub fn push(&mut self, sequence: u16, samples: Vec<f32>) -> Vec<Vec<f32>> {
+        let next = *self.next.get_or_insert(sequence);
+        let offset = sequence.wrapping_sub(next) as i16;
+        if offset.unsigned_abs() as usize >= RESYNC_DISTANCE {
+            // Far off either way, like a restarted sender, so start over from here
+            self.next = Some(sequence);
+            self.slots.clear();
+            return self.push(sequence, samples);
+        }
+        if offset < 0 {
+            // Too late, its slot was already played or concealed
+            return vec![];
+        }
+
+        let offset = offset as usize;
+        if self.slots.len() <= offset {
+            self.slots.resize(offset + 1, None);
+        }
+        self.packet_len = samples.len();
+        self.slots[offset] = Some(samples);
+
+        let depth = self.depth.div_ceil(self.packet_len.max(1));
+        let mut ready = vec![];
+        while let Some(front) = self.slots.front() {
+            if front.is_none() && self.slots.len() <= depth {
+                break;
+            }
+            let samples = self.slots.pop_front().unwrap().unwrap_or_else(|| {
+                self.lost += 1;
+                vec![0.0; self.packet_len]
+            });
+            self.next = Some(self.next.unwrap().wrapping_add(1));
+            ready.push(samples);
+        }
+        ready
+    }
+}
+
+/// Receiving thread, stopped and joined when dropped
+struct NetworkCapture {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for NetworkCapture {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Binds the listen address, joining its multicast group if it is one
+fn bind(listen: SocketAddr) -> Result<UdpSocket, anyhow::Error> {
+    let socket = match listen {
+        SocketAddr::V4(addr) if addr.ip().is_multicast() => {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, addr.port()))?;
+            socket.join_multicast_v4(addr.ip(), &Ipv4Addr::UNSPECIFIED)?;
+            socket
+        }
+        _ => UdpSocket::bind(listen)?,
+    };
+    socket.set_read_timeout(Some(READ_TIMEOUT))?;
+    Ok(socket)
+}
+
+/// Starts receiving PCM over RTP (AES67 style) or bare UDP datagrams
+pub fn open_network(
+    feed: &Feed,
+    audio_config: &AudioConfig,
+) -> Result<(Capture, usize, usize), anyhow::Error> {
+    let socket = bind(audio_config.listen)?;
+    let samplerate = audio_config.samplerate.unwrap_or(48000) as usize;
+    let channels = audio_config.channels.unwrap_or(2);
+    let depth = (audio_config.jitter * (samplerate * channels) as f32) as usize;
+
+    println!(
+        "Listening on {} for {:?} {:?}, {} ch, {}Hz",
+        audio_config.listen, audio_config.source, audio_config.net_format, channels, samplerate
+    );
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let (feed, stop) = (feed.clone(), stop.clone());
+        let rtp = audio_config.source == Source::Rtp;
+        let format = audio_config.net_format;
+        std::thread::spawn(move || receive(socket, rtp, format, channels, depth, feed, stop))
+    };
+
+    let capture = NetworkCapture {
+        stop,
+        thread: Some(thread),
+    };
+    Ok((Box::new(capture), samplerate, channels))
+}
+
+fn receive(
+    socket: UdpSocket,
+    rtp: bool,
+    format: NetFormat,
+    channels: usize,
+    depth: usize,
+    feed: Feed,
+    stop: Arc<AtomicBool>,
+) {
+    let mut jitter_buffer = JitterBuffer::new(depth);
+    let mut packet = vec![0; 65536];
+    let frame_bytes = format.bytes() * channels;
+    let mut warned = false;
+
+    while !stop.load(Ordering::Relaxed) {
+        let len = match socket.recv(&mut packet) {
+            Ok(len) => len,
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                continue
+            }
+            Err(err) => {
+                feed.fail(err);
+                return;
+            }
+        };
+
+        let (sequence, payload) = if rtp {
+            let Some(parsed) = parse_rtp(&packet[..len]) else {
+                continue;
+            };
+            parsed
+        } else {
+            (0, &packet[..len])
+        };
+        // Datagrams are read whole, so a partial frame would shift every sample
+        // after it onto the wrong channel. It is dropped instead.
+        if payload.len() % frame_bytes != 0 {
+            if !warned {
+                eprintln!(
+                    "Dropping payloads that aren't whole {channels} ch frames, like {} bytes",
+                    payload.len()
+                );
+                warned = true;
+            }
+            continue;
+        }
+
+        if !rtp {
+            feed.send(format.decode(payload));
+            continue;
+        }
+        let lost = jitter_buffer.lost;
+        for samples in jitter_buffer.push(sequence, format.decode(payload)) {
+            feed.send(samples);
+        }
+        if jitter_buffer.lost != lost {
+            feed.event(AudioEvent::PacketLoss {
+                lost: jitter_buffer.lost,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    fn rtp_packet(sequence: u16, samples: &[i16]) -> Vec<u8> {
+        let mut packet = vec![0x80, 96];
+        packet.extend(sequence.to_be_bytes());
+        packet.extend((sequence as u32 * 48).to_be_bytes());
+        packet.extend(0x1234u32.to_be_bytes());
+        for sample in samples {
+            packet.extend(sample.to_be_bytes());
+        }
+        packet
+    }
+
+    #[test]
+    fn jitter_buffer_reorders_and_conceals_lost_packets() {
+        let mut jitter_buffer = JitterBuffer::new(2);
+        let mut played = vec![];
+        for sequence in [10u16, 12, 11, 14, 15, 16, 11] {
+            played.extend(jitter_buffer.push(sequence, vec![sequence as f32]));
+        }
+        // 13 never came and was concealed once 15 arrived, the late 11 was dropped
+        let expected = [10.0, 11.0, 12.0, 0.0, 14.0, 15.0, 16.0].map(|sample| vec![sample]);
+        assert_eq!(played, expected);
+        assert_eq!(jitter_buffer.lost, 1);
+    }
+
+    #[test]
+    fn jitter_buffer_follows_sequence_wraparound() {
+        let mut jitter_buffer = JitterBuffer::new(0);
+        let played: Vec<_> = [65534u16, 65535, 0, 1]
+            .into_iter()
+            .flat_map(|sequence| jitter_buffer.push(sequence, vec![1.0]))
+            .collect();
+        assert_eq!(played.len(), 4);
+        assert_eq!(jitter_buffer.lost, 0);
+    }
+
+    #[test]
+    fn jitter_buffer_resyncs_when_the_sender_jumps_back() {
+        let mut jitter_buffer = JitterBuffer::new(0);
+        let played: Vec<_> = [30000u16, 30001, 30002, 5, 6, 7]
+            .into_iter()
+            .flat_map(|sequence| jitter_buffer.push(sequence, vec![sequence as f32]))
+            .collect();
+        let expected = [30000.0, 30001.0, 30002.0, 5.0, 6.0, 7.0].map(|sample| vec![sample]);
+        assert_eq!(played, expected);
+        assert_eq!(jitter_buffer.lost, 0);
+    }
+
+    #[test]
+    fn receives_rtp_l16_from_loopback_sender() {
+        let receiver = bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = receiver.local_addr().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let (errors, _errors_rx) = mpsc::channel();
+        let feed = Feed {
+            tx,
+            buffers: Default::default(),
+            errors,
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            std::thread::spawn(move || receive(receiver, true, NetFormat::L16, 2, 2, feed, stop))
+        };
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for sequence in [0u16, 1, 3, 4, 5] {
+            let packet = rtp_packet(sequence, &[16384, -16384]);
+            sender.send_to(&packet, addr).unwrap();
+        }
+
+        let mut buffers = vec![];
+        let mut lost = 0;
+        while let Ok(event) = rx.recv_timeout(Duration::from_secs(1)) {
+            match event {
+                AudioEvent::Buffer { buf } => buffers.push(buf),
+                AudioEvent::PacketLoss { lost: total } => lost = total,
+                _ => {}
+            }
+            if buffers.len() == 5 {
+                break;
+            }
+        }
+        stop.store(true, Ordering::Relaxed);
+        thread.join().unwrap();
+
+        assert_eq!(buffers[0], [0.5, -0.5]);
+        assert_eq!(buffers[2], [0.0, 0.0]);
+        assert_eq!(lost, 1);
+    }
+
+    #[test]
+    fn drops_raw_datagrams_of_partial_frames() {
+        let receiver = bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = receiver.local_addr().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let feed = Feed {
+            tx,
+            buffers: Default::default(),
+            errors: mpsc::channel().0,
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            std::thread::spawn(move || receive(receiver, false, NetFormat::L16, 2, 0, feed, stop))
+        };
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let samples = [16384i16, -16384, 8192, 0];
+        let frames: Vec<u8> = samples
+            .iter()
+            .flat_map(|sample| sample.to_be_bytes())
+            .collect();
+        // Half a frame, then two frames, then a frame and a half
+        for datagram in [&frames[..2], &frames[..], &frames[..6]] {
+            sender.send_to(datagram, addr).unwrap();
+        }
+        sender.send_to(&frames[..4], addr).unwrap();
+
+        let mut buffers = vec![];
+        while let Ok(event) = rx.recv_timeout(Duration::from_secs(1)) {
+            if let AudioEvent::Buffer { buf } = event {
+                buffers.push(buf);
+            }
+            if buffers.len() == 2 {
+                break;
+            }
+        }
+        stop.store(true, Ordering::Relaxed);
+        thread.join().unwrap();
+
+        // Left and right stay in place, the partial datagrams never got through
+        assert_eq!(buffers, [vec![0.5, -0.5, 0.25, 0.0], vec![0.5, -0.5]]);
+    }
+}
@@ -0,0 +1,296 @@
+use std::{
+    fs::File,
+    io::{ErrorKind, Read},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::bail;
+use cpal::{FromSample, Sample, SampleFormat};
+
+use super::{AudioConfig, Capture, EndOfInput, Feed};
+
+const CHUNK_FRAMES: usize = 1024;
+/// How far ahead of real time input may get before reading is held back
+const MAX_AHEAD: Duration = Duration::from_millis(100);
+
+type Decoder = Box<dyn Fn(&[u8]) -> Vec<f32> + Send>;
+
+/// The one thread reading the input. It outlives captures: a second reader on the
+/// same stdin or FIFO, left blocked in a read by a dropped capture, would take
+/// bytes from the middle of frames. A reopened capture attaches to it instead.
+struct Reader {
+    path: Option<String>,
+    /// Capture fed at the moment, input read while none is attached is dropped
+    feed: Mutex<Option<Feed>>,
+    /// Set once stdin is finished, it can't be opened again
+    ended: AtomicBool,
+}
+
+static READER: Mutex<Option<Arc<Reader>>> = Mutex::new(None);
+
+/// Detaches from the reader when dropped
+struct PipeCapture {
+    reader: Arc<Reader>,
+}
+
+impl Drop for PipeCapture {
+    fn drop(&mut self) {
+        *self.reader.feed.lock().unwrap() = None;
+    }
+}
+
+fn decode_samples<T, const N: usize>(bytes: &[u8], from_bytes: fn([u8; N]) -> T) -> Vec<f32>
+where
+    T: Sample,
+    f32: FromSample<T>,
+{
+    bytes
+        .chunks_exact(N)
+        .map(|sample| from_bytes(sample.try_into().unwrap()).to_sample())
+        .collect()
+}
+
+fn decoder(format: SampleFormat, big_endian: bool) -> Result<Decoder, anyhow::Error> {
+    macro_rules! decoder {
+        ($t:ty) => {{
+            let from_bytes = if big_endian {
+                <$t>::from_be_bytes
+            } else {
+                <$t>::from_le_bytes
+            };
+            Box::new(move |bytes: &[u8]| decode_samples(bytes, from_bytes))
+        }};
+    }
+
+    Ok(match format {
+        SampleFormat::I8 => decoder!(i8),
+        SampleFormat::I16 => decoder!(i16),
+        SampleFormat::I32 => decoder!(i32),
+        SampleFormat::I64 => decoder!(i64),
+        SampleFormat::U8 => decoder!(u8),
+        SampleFormat::U16 => decoder!(u16),
+        SampleFormat::U32 => decoder!(u32),
+        SampleFormat::U64 => decoder!(u64),
+        SampleFormat::F32 => decoder!(f32),
+        SampleFormat::F64 => decoder!(f64),
+        format => bail!("unsupported sample format '{format}'"),
+    })
+}
+
+/// Starts reading raw interleaved PCM from stdin or a named pipe, or attaches to
+/// the reader already doing so. A pipe is reopened by the supervisor once its writer
+/// goes away, so the next writer picks up. The end of stdin is final.
+pub fn open_pipe(
+    feed: &Feed,
+    audio_config: &AudioConfig,
+) -> Result<(Capture, usize, usize), anyhow::Error> {
+    let samplerate = audio_config.samplerate.unwrap_or(48000) as usize;
+    let channels = audio_config.channels.unwrap_or(2);
+    let format = audio_config.sample_format.unwrap_or(SampleFormat::F32);
+    let decode = decoder(format, audio_config.big_endian)?;
+    let frame_bytes = format.sample_size() * channels;
+
+    let mut current = READER.lock().unwrap();
+    if let Some(reader) = current
+        .as_ref()
+        .filter(|reader| reader.path == audio_config.pipe)
+    {
+        if reader.ended.load(Ordering::Relaxed) {
+            return Err(EndOfInput.into());
+        }
+        *reader.feed.lock().unwrap() = Some(feed.clone());
+        let capture = PipeCapture {
+            reader: reader.clone(),
+        };
+        return Ok((Box::new(capture), samplerate, channels));
+    }
+
+    println!(
+        "Reading {}{} from {}, {} ch, {}Hz",
+        format,
+        if audio_config.big_endian { "be" } else { "le" },
+        audio_config.pipe.as_deref().unwrap_or("stdin"),
+        channels,
+        samplerate
+    );
+
+    let reader = Arc::new(Reader {
+        path: audio_config.pipe.clone(),
+        feed: Mutex::new(Some(feed.clone())),
+        ended: AtomicBool::new(false),
+    });
+    *current = Some(reader.clone());
+    {
+        let reader = reader.clone();
+        std::thread::spawn(move || run_reader(&reader, &decode, frame_bytes, samplerate));
+    }
+
+    Ok((Box::new(PipeCapture { reader }), samplerate, channels))
+}
+
+fn run_reader(reader: &Arc<Reader>, decode: &Decoder, frame_bytes: usize, samplerate: usize) {
+    let fail = |err: String| {
+        if let Some(feed) = &*reader.feed.lock().unwrap() {
+            feed.fail(err);
+        }
+    };
+
+    // Opening a FIFO blocks until a writer shows up, so it happens here
+    let input: Box<dyn Read> = match &reader.path {
+        Some(path) => match File::open(path) {
+            Ok(file) => Box::new(file),
+            Err(err) => {
+                fail(format!("cannot open '{path}': {err}"));
+                return detach(reader);
+            }
+        },
+        None => Box::new(std::io::stdin()),
+    };
+
+    let result = read_pcm(input, decode, frame_bytes, samplerate, &reader.feed);
+    if reader.path.is_none() {
+        // Before the failure goes out, so the reopen it causes finds stdin ended
+        reader.ended.store(true, Ordering::Relaxed);
+    }
+    match result {
+        Ok(()) => fail(EndOfInput.to_string()),
+        Err(err) => fail(err.to_string()),
+    }
+    if reader.path.is_some() {
+        // The next capture opens the FIFO again, for its next writer
+        detach(reader);
+    }
+}
+
+/// Lets the next open_pipe start a new reader
+fn detach(reader: &Arc<Reader>) {
+    let mut current = READER.lock().unwrap();
+    if current
+        .as_ref()
+        .is_some_and(|current| Arc::ptr_eq(current, reader))
+    {
+        *current = None;
+    }
+}
+
+/// Reads until the end of input, sending whole frames to the attached feed. Partial
+/// frames stay buffered across captures, so frames never slip.
+fn read_pcm(
+    mut input: impl Read,
+    decode: &Decoder,
+    frame_bytes: usize,
+    samplerate: usize,
+    feed: &Mutex<Option<Feed>>,
+) -> Result<(), std::io::Error> {
+    let mut buf = vec![0; frame_bytes * CHUNK_FRAMES];
+    let mut filled = 0;
+    let started = Instant::now();
+    let mut frames = 0;
+
+    loop {
+        let read = match input.read(&mut buf[filled..]) {
+            Ok(0) => return Ok(()),
+            Ok(read) => read,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+
+        filled += read;
+        let whole = filled - filled % frame_bytes;
+        if whole == 0 {
+            continue;
+        }
+        if let Some(feed) = &*feed.lock().unwrap() {
+            feed.send(decode(&buf[..whole]));
+        }
+        buf.copy_within(whole..filled, 0);
+        filled -= whole;
+
+        // Input faster than real time, like a file piped through sox, is held back
+        // so the needles move as they would on playback
+        frames += whole / frame_bytes;
+        let position = Duration::from_secs_f64(frames as f64 / samplerate as f64);
+        let ahead = position.saturating_sub(started.elapsed());
+        if ahead > MAX_AHEAD {
+            std::thread::sleep(ahead - MAX_AHEAD);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, sync::mpsc};
+
+    use crate::AudioEvent;
+
+    use super::*;
+
+    #[test]
+    fn reads_whole_frames_of_big_endian_pcm() {
+        let (tx, rx) = mpsc::channel();
+        let feed = Feed {
+            tx,
+            buffers: Default::default(),
+            errors: mpsc::channel().0,
+        };
+
+        let mut bytes: Vec<u8> = [16384i16, -16384, 8192, 0]
+            .iter()
+            .flat_map(|sample| sample.to_be_bytes())
+            .collect();
+        // A trailing partial frame is never sent
+        bytes.push(0x7f);
+        let decode = decoder(SampleFormat::I16, true).unwrap();
+        let feed = Mutex::new(Some(feed));
+        read_pcm(Cursor::new(bytes), &decode, 4, 48000, &feed).unwrap();
+
+        let Ok(AudioEvent::Buffer { buf }) = rx.try_recv() else {
+            panic!("no buffer read");
+        };
+        assert_eq!(buf, [0.5, -0.5, 0.25, 0.0]);
+        assert!(rx.try_recv().is_err());
+    }
+
+    /// Input arriving in odd sized pieces, as from a pipe
+    struct Dribble(Vec<u8>);
+
+    impl Read for Dribble {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = self.0.len().min(3).min(buf.len());
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0.drain(..len);
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn frames_stay_aligned_across_partial_reads() {
+        let (tx, rx) = mpsc::channel();
+        let feed = Feed {
+            tx,
+            buffers: Default::default(),
+            errors: mpsc::channel().0,
+        };
+        let samples: Vec<i16> = (0..40).map(|n| n * 256).collect();
+        let bytes = samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        let decode = decoder(SampleFormat::I16, false).unwrap();
+        read_pcm(Dribble(bytes), &decode, 4, 48000, &Mutex::new(Some(feed))).unwrap();
+
+        let read: Vec<f32> = rx
+            .try_iter()
+            .flat_map(|event| match event {
+                AudioEvent::Buffer { buf } => buf,
+                _ => vec![],
+            })
+            .collect();
+        let expected: Vec<f32> = samples.iter().map(|sample| sample.to_sample()).collect();
+        assert_eq!(read, expected);
+    }
+}
@@ -0,0 +1,308 @@
+use std::{f32::consts::PI, str::FromStr};
+
+use anyhow::{anyhow, bail};
+use biquad::{Biquad, Coefficients, DirectForm2Transposed, ToHertz, Q_BUTTERWORTH_F32};
+
+use crate::needle::{Movement, Needle};
+
+/// Highest cutoff accepted for filter ballistics, well below any control rate
+const MAX_CUTOFF: f32 = 100.0;
+
+/// Smooths needle deflection, run once per control period
+pub trait MotionFilter {
+    /// Feeds the target deflection in, returns where the needle is now
+    fn process(&mut self, input: f32) -> f32;
+
+    /// Puts the needle at rest at `position`, as if held there by a steady input
+    fn settle(&mut self, position: f32);
+}
+
+/// Needle motion filter choice, cutoffs in Hz
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ballistics {
+    OnePole(f32),
+    /// Two one-pole filters in series
+    Cascaded(f32),
+    /// Butterworth lowpass biquad
+    Biquad(f32),
+    /// Mass-spring-damper needle model
+    Mechanical(Movement),
+}
+
+impl Ballistics {
+    pub fn name(&self) -> String {
+        match self {
+            Ballistics::OnePole(cutoff) => format!("1-POLE {cutoff}Hz"),
+            Ballistics::Cascaded(cutoff) => format!("2x1-POLE {cutoff}Hz"),
+            Ballistics::Biquad(cutoff) => format!("BIQUAD {cutoff}Hz"),
+            Ballistics::Mechanical(movement) => movement.name().to_string(),
+        }
+    }
+
+    /// Steps through movement presets, then the filters at the default cutoff
+    pub fn next(&self) -> Self {
+        match self {
+            Ballistics::Mechanical(Movement::Loose) => Ballistics::Biquad(4.0),
+            Ballistics::Mechanical(movement) => Ballistics::Mechanical(movement.next()),
+            Ballistics::Biquad(cutoff) => Ballistics::Cascaded(*cutoff),
+            Ballistics::Cascaded(cutoff) => Ballistics::OnePole(*cutoff),
+            Ballistics::OnePole(_) => Ballistics::Mechanical(Movement::SifamVu),
+        }
+    }
+
+    pub fn filter(&self, sample_rate: f32) -> Box<dyn MotionFilter> {
+        match *self {
+            Ballistics::OnePole(cutoff) => Box::new(LowPassFilter::new(cutoff, sample_rate)),
+            Ballistics::Cascaded(cutoff) => {
+                Box::new(SecondOrderLowPassFilter::new(cutoff, sample_rate))
+            }
+            Ballistics::Biquad(cutoff) => Box::new(BiquadFilter::new(cutoff, sample_rate)),
+            Ballistics::Mechanical(movement) => Box::new(Needle::new(movement, sample_rate)),
+        }
+    }
+}
+
+/// Parses `one-pole:<Hz>`, `cascaded:<Hz>`, `biquad:<Hz>` or a needle movement
+/// such as `sifam-vu`, optionally as `mechanical:<movement>`
+impl FromStr for Ballistics {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((kind, param)) = s.split_once(':') else {
+            return Ok(Ballistics::Mechanical(s.parse()?));
+        };
+        if kind == "mechanical" {
+            return Ok(Ballistics::Mechanical(param.parse()?));
+        }
+
+        let cutoff: f32 = param
+            .trim_end_matches("Hz")
+            .parse()
+            .map_err(|_| anyhow!("invalid cutoff '{param}'"))?;
+        if cutoff <= 0.0 || cutoff > MAX_CUTOFF {
+            bail!("cutoff must be above 0 and at most {MAX_CUTOFF}Hz, got '{param}'");
+        }
+
+        match kind {
+            "one-pole" => Ok(Ballistics::OnePole(cutoff)),
+            "cascaded" => Ok(Ballistics::Cascaded(cutoff)),
+            "biquad" => Ok(Ballistics::Biquad(cutoff)),
+            _ => Err(anyhow!("unknown ballistics '{kind}'")),
+        }
+    }
+}
+
+/// A simple first-order low-pass filter
+#[derive(Debug)]
+pub struct LowPassFilter {
+    /// Cutoff frequency in Hz
+    cutoff: f32,
+    /// Sample rate in Hz
+    sample_rate: f32,
+    /// Filter coefficient
+    alpha: f32,
+    /// Previous output for feedback
+    prev_output: f32,
+}
+
+impl LowPassFilter {
+    /// Creates a new LowPassFilter with a given cutoff frequency and sample rate
+    pub fn new(cutoff: f32, sample_rate: f32) -> Self {
+        let mut filter = LowPassFilter {
+            cutoff,
+            sample_rate,
+            alpha: 0.0,
+            prev_output: 0.0,
+        };
+        filter.update_alpha();
+        filter
+    }
+
+    /// Update the alpha value based on the cutoff frequency and sample rate
+    fn update_alpha(&mut self) {
+        let rc = 1.0 / (2.0 * PI * self.cutoff);
+        let dt = 1.0 / self.sample_rate;
+        self.alpha = dt / (rc + dt);
+    }
+}
+
+impl MotionFilter for LowPassFilter {
+    fn process(&mut self, input: f32) -> f32 {
+        let output = self.alpha * input + (1.0 - self.alpha) * self.prev_output;
+        self.prev_output = output;
+        output
+    }
+
+    fn settle(&mut self, position: f32) {
+        self.prev_output = position;
+    }
+}
+
+/// A second-order low-pass filter implemented by cascading two first-order filters
+#[derive(Debug)]
+pub struct SecondOrderLowPassFilter {
+    filter1: LowPassFilter,
+    filter2: LowPassFilter,
+}
+
+impl SecondOrderLowPassFilter {
+    pub fn new(cutoff: f32, sample_rate: f32) -> Self {
+        SecondOrderLowPassFilter {
+            filter1: LowPassFilter::new(cutoff, sample_rate),
+            filter2: LowPassFilter::new(cutoff, sample_rate),
+        }
+    }
+}
+
+impl MotionFilter for SecondOrderLowPassFilter {
+    fn process(&mut self, input: f32) -> f32 {
+        let temp = self.filter1.process(input);
+        self.filter2.process(temp)
+    }
+
+    fn settle(&mut self, position: f32) {
+        self.filter1.settle(position);
+        self.filter2.settle(position);
+    }
+}
+
+/// Butterworth lowpass, the classic smooth needle with slight overshoot
+pub struct BiquadFilter {
+    filter: DirectForm2Transposed<f32>,
+    /// Kept for settling, the filter doesn't expose them
+    coefficients: Coefficients<f32>,
+}
+
+impl BiquadFilter {
+    pub fn new(cutoff: f32, sample_rate: f32) -> Self {
+        let coefficients = Coefficients::<f32>::from_params(
+            biquad::Type::LowPass,
+            sample_rate.hz(),
+            cutoff.hz(),
+            Q_BUTTERWORTH_F32,
+        )
+        .unwrap();
+        Self {
+            filter: DirectForm2Transposed::<f32>::new(coefficients),
+            coefficients,
+        }
+    }
+}
+
+impl MotionFilter for BiquadFilter {
+    fn process(&mut self, input: f32) -> f32 {
+        self.filter.run(input)
+    }
+
+    fn settle(&mut self, position: f32) {
+        // Steady state with input and output at `position`, the lowpass having unity DC gain
+        let Coefficients { a2, b0, b2, .. } = self.coefficients;
+        self.filter.s1 = position * (1.0 - b0);
+        self.filter.s2 = position * (b2 - a2);
+    }
+}
+
+impl MotionFilter for Needle {
+    fn process(&mut self, input: f32) -> f32 {
+        self.tick(input)
+    }
+
+    fn settle(&mut self, position: f32) {
+        self.rest_at(position);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::needle::FULL_SCALE_STOP;
+
+    const SAMPLE_RATE: f32 = 1000.0;
+
+    /// Unit step response over `seconds`
+    fn step(ballistics: Ballistics, seconds: f32) -> Vec<f32> {
+        let mut filter = ballistics.filter(SAMPLE_RATE);
+        (0..(seconds * SAMPLE_RATE) as usize)
+            .map(|_| filter.process(1.0))
+            .collect()
+    }
+
+    fn time_to_reach(response: &[f32], level: f32) -> f32 {
+        let idx = response.iter().position(|x| *x >= level).unwrap();
+        (idx + 1) as f32 / SAMPLE_RATE
+    }
+
+    fn overshoot(response: &[f32]) -> f32 {
+        response.iter().fold(0.0f32, |max, x| max.max(*x)) - 1.0
+    }
+
+    #[test]
+    fn one_pole_step_reaches_63_percent_after_time_constant() {
+        let response = step(Ballistics::OnePole(4.0), 1.0);
+        let time_constant = 1.0 / (2.0 * PI * 4.0);
+        assert!((time_to_reach(&response, 1.0 - (-1.0f32).exp()) - time_constant).abs() < 0.002);
+        assert!(response.windows(2).all(|w| w[1] >= w[0]));
+        assert!((response.last().unwrap() - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn cascaded_step_is_slower_than_one_pole_without_overshoot() {
+        let response = step(Ballistics::Cascaded(4.0), 1.0);
+        let one_pole = step(Ballistics::OnePole(4.0), 1.0);
+        assert!(time_to_reach(&response, 0.9) > time_to_reach(&one_pole, 0.9));
+        assert!(overshoot(&response) <= 0.0);
+        assert!((response.last().unwrap() - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn biquad_step_overshoots_like_butterworth() {
+        let response = step(Ballistics::Biquad(4.0), 1.0);
+        // Second order Butterworth overshoots a step by 4.3%
+        let overshoot = overshoot(&response);
+        assert!((overshoot - 0.043).abs() < 0.005, "overshoot {overshoot}");
+        assert!((response.last().unwrap() - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn mechanical_step_matches_vu_movement() {
+        let response = step(Ballistics::Mechanical(Movement::SifamVu), 2.0);
+        let rise = time_to_reach(&response, 0.99);
+        assert!((rise - 0.3).abs() < 0.03, "99% after {rise}s");
+        let overshoot = overshoot(&response);
+        assert!(
+            overshoot > 0.01 && overshoot < 0.015,
+            "overshoot {overshoot}"
+        );
+        assert!((response.last().unwrap() - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn mechanical_needle_stops_at_the_pin() {
+        for movement in [Movement::SifamVu, Movement::SifamPpm, Movement::Loose] {
+            let mut filter = Ballistics::Mechanical(movement).filter(SAMPLE_RATE);
+            let slammed: Vec<f32> = (0..1000).map(|_| filter.process(3.0)).collect();
+            assert!(slammed.iter().all(|x| *x <= FULL_SCALE_STOP));
+            assert_eq!(*slammed.last().unwrap(), FULL_SCALE_STOP);
+        }
+    }
+
+    #[test]
+    fn settled_needle_stays_put() {
+        for ballistics in [
+            Ballistics::OnePole(4.0),
+            Ballistics::Cascaded(4.0),
+            Ballistics::Biquad(4.0),
+            Ballistics::Mechanical(Movement::SifamVu),
+        ] {
+            let mut filter = ballistics.filter(SAMPLE_RATE);
+            filter.settle(0.6);
+            for _ in 0..1000 {
+                let position = filter.process(0.6);
+                assert!(
+                    (position - 0.6).abs() < 1e-5,
+                    "{ballistics:?} at {position}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use femtovg::{renderer::OpenGl, Align, Canvas, Color, FontId, Paint, Path};
+
+use crate::{readouts::format_db, VU_WIDTH};
+
+/// Oldest events are dropped past this many
+const MAX_EVENTS: usize = 10000;
+const OVERLAY_ROWS: usize = 12;
+const ROW_H: f32 = 11.0;
+const OVERLAY_W: f32 = 300.0;
+const OVERLAY_Y: f32 = 40.0;
+
+/// A run of overloaded samples on one meter
+#[derive(Clone, Debug)]
+pub struct ClipEvent {
+    pub channel: usize,
+    /// Wall clock time the event started
+    pub timestamp: SystemTime,
+    /// Frames into piped input, None for live inputs
+    pub position: Option<u64>,
+    /// Rate the position counts frames at
+    pub samplerate: usize,
+    pub duration: f32,
+    /// Highest sample level during the event, relative to full scale
+    pub peak: f32,
+}
+
+/// Overload events, recorded as the overload detector fires
+#[derive(Default)]
+pub struct ClipLog {
+    events: VecDeque<ClipEvent>,
+    /// Events ever recorded, including ones since dropped
+    recorded: u64,
+    /// Events still going on per meter, with their length so far in samples
+    open: [Option<(ClipEvent, u64)>; 2],
+}
+
+impl ClipLog {
+    /// Called every sample with the detector output and the sample level
+    pub fn update(
+        &mut self,
+        channel: usize,
+        overloaded: bool,
+        level: f32,
+        position: Option<u64>,
+        samplerate: usize,
+    ) {
+        match (&mut self.open[channel], overloaded) {
+            (Some((event, samples)), true) => {
+                *samples += 1;
+                event.duration = *samples as f32 / samplerate as f32;
+                event.peak = event.peak.max(level);
+            }
+            (Some(_), false) => {
+                let (event, _) = self.open[channel].take().unwrap();
+                if self.events.len() >= MAX_EVENTS {
+                    self.events.pop_front();
+                }
+                self.events.push_back(event);
+                self.recorded += 1;
+            }
+            (None, true) => {
+                let event = ClipEvent {
+                    channel,
+                    timestamp: SystemTime::now(),
+                    position,
+                    samplerate,
+                    duration: 1.0 / samplerate as f32,
+                    peak: level,
+                };
+                self.open[channel] = Some((event, 1));
+            }
+            (None, false) => {}
+        }
+    }
+
+    /// Finished events, oldest first
+    pub fn events(&self) -> &VecDeque<ClipEvent> {
+        &self.events
+    }
+
+    /// Finished events, then the ones still going on flagged true, their length so far
+    fn with_open(&self) -> impl DoubleEndedIterator<Item = (&ClipEvent, bool)> {
+        let open = self.open.iter().flatten().map(|(event, _)| (event, true));
+        self.events.iter().map(|event| (event, false)).chain(open)
+    }
+
+    pub fn recorded(&self) -> u64 {
+        self.recorded
+    }
+
+    /// Positions are left empty for live inputs
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "channel,timestamp,position,position_seconds,duration,peak_db,in_progress\n",
+        );
+        for (event, in_progress) in self.with_open() {
+            writeln!(
+                csv,
+                "{},{:.3},{},{},{:.4},{},{}",
+                event.channel + 1,
+                unix_seconds(event.timestamp),
+                event
+                    .position
+                    .map_or(String::new(), |position| position.to_string()),
+                event.position.map_or(String::new(), |position| {
+                    format!("{:.3}", position as f64 / event.samplerate as f64)
+                }),
+                event.duration,
+                format_db(event.peak),
+                in_progress,
+            )
+            .unwrap();
+        }
+        csv
+    }
+}
+
+pub fn unix_seconds(timestamp: SystemTime) -> f64 {
+    timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// UTC time of day as `HH:MM:SS.mmm`
+fn format_time(timestamp: SystemTime) -> String {
+    let seconds = unix_seconds(timestamp);
+    let day_seconds = seconds % 86400.0;
+    format!(
+        "{:02}:{:02}:{:06.3}",
+        (day_seconds / 3600.0) as u32,
+        (day_seconds % 3600.0 / 60.0) as u32,
+        day_seconds % 60.0
+    )
+}
+
+pub fn draw_clip_log_overlay(canvas: &mut Canvas<OpenGl>, font_ids: &[FontId], log: &ClipLog) {
+    let x = VU_WIDTH - OVERLAY_W / 2.0;
+    let y = OVERLAY_Y;
+
+    let mut path = Path::new();
+    path.rounded_rect(
+        x - 8.0,
+        y - 16.0,
+        OVERLAY_W + 16.0,
+        ROW_H * (OVERLAY_ROWS + 1) as f32 + 24.0,
+        4.0,
+    );
+    canvas.fill_path(&path, &Paint::color(Color::rgba(20, 18, 18, 230)));
+
+    let mut paint = Paint::color(Color::rgb(220, 220, 220));
+    paint.set_font(&[font_ids[0]]);
+    paint.set_font_size(8.0);
+    paint.set_text_align(Align::Left);
+    canvas
+        .fill_text(
+            x,
+            y - 4.0,
+            format!(
+                "CLIP LOG  {} events  E: export CSV",
+                log.with_open().count()
+            ),
+            &paint,
+        )
+        .unwrap();
+
+    let columns = [0.0, 24.0, 110.0, 190.0, 250.0];
+    for (column, header) in columns
+        .iter()
+        .zip(["CH", "TIME (UTC)", "POSITION", "LENGTH", "PEAK"])
+    {
+        canvas
+            .fill_text(x + column, y + ROW_H, header, &paint)
+            .unwrap();
+    }
+
+    let newest = log.with_open().rev().take(OVERLAY_ROWS);
+    for (row, (event, in_progress)) in newest.enumerate() {
+        let row_y = y + ROW_H * (row + 2) as f32;
+        // Events still going on are lit like the lamp
+        paint.set_color(if in_progress {
+            Color::rgb(255, 48, 0)
+        } else {
+            Color::rgb(255, 200, 160)
+        });
+        let cells = [
+            (event.channel + 1).to_string(),
+            format_time(event.timestamp),
+            event
+                .position
+                .map_or("-".into(), |position| position.to_string()),
+            format!("{:.1}ms", event.duration * 1000.0),
+            format_db(event.peak),
+        ];
+        for (column, cell) in columns.iter().zip(cells) {
+            canvas.fill_text(x + column, row_y, cell, &paint).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_going_on_are_exported_with_their_length_so_far() {
+        let mut log = ClipLog::default();
+        let overloads = [false, true, true, false, false, true, true, true];
+        for (position, overloaded) in overloads.into_iter().enumerate() {
+            log.update(0, overloaded, 1.0, Some(position as u64), 1000);
+        }
+
+        assert_eq!(log.events().len(), 1);
+        assert_eq!(log.events()[0].position, Some(1));
+        assert_eq!(log.events()[0].duration, 0.002);
+
+        let csv = log.to_csv();
+        let rows: Vec<_> = csv.lines().skip(1).collect();
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0].contains(",1,0.001,0.0020,") && rows[0].ends_with(",false"));
+        assert!(rows[1].contains(",5,0.005,0.0030,") && rows[1].ends_with(",true"));
+    }
+
+    #[test]
+    fn oldest_events_are_dropped() {
+        let mut log = ClipLog::default();
+        for position in 0..(MAX_EVENTS as u64 + 5) * 2 {
+            log.update(1, position % 2 == 0, 1.0, Some(position), 48000);
+        }
+        assert_eq!(log.events().len(), MAX_EVENTS);
+        assert_eq!(log.recorded(), MAX_EVENTS as u64 + 5);
+        assert_eq!(log.events()[0].position, Some(10));
+    }
+
+    #[test]
+    fn positions_are_in_seconds_at_their_own_rate() {
+        let mut log = ClipLog::default();
+        for (position, samplerate) in [(48000, 48000), (44100, 44100)] {
+            log.update(0, true, 1.0, Some(position), samplerate);
+            log.update(0, false, 0.0, Some(position + 1), samplerate);
+        }
+        log.update(1, true, 1.0, None, 48000);
+        log.update(1, false, 0.0, None, 48000);
+
+        let csv = log.to_csv();
+        let rows: Vec<_> = csv.lines().skip(1).collect();
+        assert!(rows[0].contains(",48000,1.000,"));
+        assert!(rows[1].contains(",44100,1.000,"));
+        assert!(rows[2].contains(",,,"));
+    }
+}
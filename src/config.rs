@@ -0,0 +1,298 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail};
+
+use crate::{
+    audio::{parse_raw_format, AudioConfig, Source},
+    ballistics::Ballistics,
+    fft::{WindowFunction, FFT_SIZES},
+    integrator::Integration,
+    needle::Movement,
+    osc::OscConfig,
+    overload::OverloadTrigger,
+    routing::Routing,
+    stereo_mode::StereoMode,
+    telemetry::TelemetryConfig,
+    weighting::Weighting,
+};
+
+/// Runtime settings, taken from `--key value` command line options.
+/// `--config <file>` applies `key = value` lines from a file in place.
+pub struct Config {
+    /// Time span of the history panel in seconds
+    pub history_span: f32,
+    /// dB range shown by the history panel below 0dBFS
+    pub history_db_range: f32,
+    /// Integration time of the correlation meter in seconds
+    pub correlation_time: f32,
+    /// How long goniometer traces take to fade out, in seconds
+    pub goniometer_persistence: f32,
+    /// Spectrum analyzer FFT length in samples
+    pub fft_size: usize,
+    pub fft_window: WindowFunction,
+    /// Spectrum averaging time constant in seconds, 0 to disable
+    pub spectrum_averaging: f32,
+    /// How long spectrum peaks hold before falling back, in seconds
+    pub spectrum_peak_hold: f32,
+    /// Channel matrix the meters start in
+    pub stereo_mode: StereoMode,
+    /// Input channels summed onto each meter
+    pub routing: Routing,
+    /// Frequency weighting per meter
+    pub weighting: [Weighting; 2],
+    /// RMS integration per meter. The default 20ms window is short enough for the
+    /// VU movement to set IEC 60268-17 ballistics.
+    pub integration: [Integration; 2],
+    /// Needle motion filter per meter
+    pub ballistics: [Ballistics; 2],
+    pub overload_trigger: OverloadTrigger,
+    /// Overload lamp threshold in dBFS, on the input before the preamp. The default
+    /// lights the RMS lamp at 0VU with the default 18dB preamp.
+    pub overload_threshold: f32,
+    /// Keep the overload lamp lit until it is clicked
+    pub overload_latch: bool,
+    /// Where the clip log is exported as CSV
+    pub clip_log_path: String,
+    /// Capture backend and device
+    pub audio: AudioConfig,
+    /// Print capture devices and exit
+    pub list_devices: bool,
+    /// Remote control and level publishing
+    pub osc: OscConfig,
+    /// Embedded HTTP and WebSocket server for remote dashboards
+    pub telemetry: TelemetryConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            history_span: 60.0,
+            history_db_range: 60.0,
+            correlation_time: 0.3,
+            goniometer_persistence: 0.3,
+            fft_size: 4096,
+            fft_window: WindowFunction::Hann,
+            spectrum_averaging: 0.1,
+            spectrum_peak_hold: 1.0,
+            stereo_mode: StereoMode::LeftRight,
+            routing: Routing::default(),
+            weighting: [Weighting::Z; 2],
+            integration: [Integration::Rectangular(0.02); 2],
+            ballistics: [Ballistics::Mechanical(Movement::SifamVu); 2],
+            overload_trigger: OverloadTrigger::Rms,
+            overload_threshold: -18.0,
+            overload_latch: false,
+            clip_log_path: "clips.csv".into(),
+            audio: AudioConfig::default(),
+            list_devices: false,
+            osc: OscConfig::default(),
+            telemetry: TelemetryConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args() -> Result<Self, anyhow::Error> {
+        let mut config = Self::default();
+        let mut args = std::env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            let key = arg
+                .strip_prefix("--")
+                .ok_or_else(|| anyhow!("unexpected argument '{arg}'"))?;
+            if FLAGS.contains(&key) {
+                config.apply(key, "true")?;
+                continue;
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| anyhow!("missing value for '--{key}'"))?;
+            if key == "config" {
+                config.apply_file(&value)?;
+            } else {
+                config.apply(key, &value)?;
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Applies `key = value` lines, blank lines and `#` comments are skipped
+    pub fn apply_file(&mut self, path: &str) -> Result<(), anyhow::Error> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| anyhow!("cannot read config '{path}': {err}"))?;
+
+        for (idx, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("{path}:{}: expected 'key = value'", idx + 1))?;
+            self.apply(key.trim(), value.trim())
+                .map_err(|err| anyhow!("{path}:{}: {err}", idx + 1))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn apply(&mut self, key: &str, value: &str) -> Result<(), anyhow::Error> {
+        match key {
+            "history-span" => self.history_span = parse_positive(key, value)?,
+            "history-range" => self.history_db_range = parse_positive(key, value)?,
+            "correlation-time" => self.correlation_time = parse_positive(key, value)?,
+            "goniometer-persistence" => self.goniometer_persistence = parse_positive(key, value)?,
+            "fft-size" => {
+                let size = parse(key, value)?;
+                if !FFT_SIZES.contains(&size) {
+                    bail!("'--{key}' must be one of {FFT_SIZES:?}, got '{value}'");
+                }
+                self.fft_size = size;
+            }
+            "fft-window" => self.fft_window = value.parse()?,
+            "spectrum-averaging" => self.spectrum_averaging = parse_non_negative(key, value)?,
+            "spectrum-peak-hold" => self.spectrum_peak_hold = parse_non_negative(key, value)?,
+            "mode" => self.stereo_mode = value.parse()?,
+            "routing" => self.routing = value.parse()?,
+            "weighting" => self.weighting = parse_per_meter(value)?,
+            "integration" => self.integration = parse_per_meter(value)?,
+            "ballistics" => self.ballistics = parse_per_meter(value)?,
+            "overload-trigger" => self.overload_trigger = value.parse()?,
+            "overload-threshold" => self.overload_threshold = parse(key, value)?,
+            "overload-latch" => self.overload_latch = parse(key, value)?,
+            "clip-log" => self.clip_log_path = value.into(),
+            "source" => self.audio.source = value.parse()?,
+            "host" => self.audio.host = value.parse()?,
+            "device" => self.audio.device = Some(value.into()),
+            "rate" => self.audio.samplerate = Some(parse_count(key, value)?),
+            "buffer-size" => self.audio.buffer_size = Some(parse_count(key, value)?),
+            "format" => {
+                let (format, big_endian) = parse_raw_format(value)?;
+                self.audio.sample_format = Some(format);
+                self.audio.big_endian = big_endian;
+            }
+            "channels" => self.audio.channels = Some(parse_count(key, value)?),
+            "listen" => self.audio.listen = parse(key, value)?,
+            "net-format" => self.audio.net_format = value.parse()?,
+            "jitter" => self.audio.jitter = parse_non_negative(key, value)?,
+            "stdin" => {
+                if parse(key, value)? {
+                    self.audio.source = Source::Stdin;
+                }
+            }
+            "pipe" => {
+                self.audio.source = Source::Pipe;
+                self.audio.pipe = Some(value.into());
+            }
+            "signal" => {
+                self.audio.source = Source::Generator;
+                self.audio.generator.signal = value.parse()?;
+            }
+            "frequency" => self.audio.generator.frequency = parse_positive(key, value)?,
+            "level" => {
+                self.audio.generator.level = parse(key, value)?;
+                if !self.audio.generator.level.is_finite() {
+                    bail!("invalid value '{value}' for '--{key}'");
+                }
+            }
+            "sweep" => {
+                let (from, to) = value
+                    .split_once(',')
+                    .ok_or_else(|| anyhow!("'--{key}' takes '<from>,<to>' in Hz"))?;
+                self.audio.generator.sweep = (parse_positive(key, from)?, parse_positive(key, to)?);
+            }
+            "sweep-time" => self.audio.generator.sweep_time = parse_positive(key, value)?,
+            "burst" => {
+                let (on, period) = value
+                    .split_once(',')
+                    .ok_or_else(|| anyhow!("'--{key}' takes '<on>,<period>' in seconds"))?;
+                self.audio.generator.burst =
+                    (parse_positive(key, on)?, parse_positive(key, period)?);
+            }
+            "play" => self.audio.generator.play = parse(key, value)?,
+            "output" => {
+                self.audio.generator.play = true;
+                self.audio.generator.output = Some(value.into());
+            }
+            "jack-name" => self.audio.jack_name = value.into(),
+            "jack-ports" => {
+                self.audio.jack_ports = value.split(',').map(|port| port.trim().into()).collect();
+                if self.audio.jack_ports.iter().any(String::is_empty) {
+                    bail!("'--{key}' needs comma separated port names, got '{value}'");
+                }
+            }
+            "osc-listen" => self.osc.listen = Some(parse(key, value)?),
+            "osc-send" => self.osc.send = Some(parse(key, value)?),
+            "osc-rate" => self.osc.rate = parse_positive(key, value)?,
+            "telemetry" => self.telemetry.listen = Some(parse(key, value)?),
+            "telemetry-rate" => self.telemetry.rate = parse_positive(key, value)?,
+            "list-devices" => self.list_devices = parse(key, value)?,
+            _ => bail!("unknown option '--{key}'"),
+        }
+        Ok(())
+    }
+}
+
+/// Options given on the command line without a value, `key = true` in files
+const FLAGS: &[&str] = &["list-devices", "stdin", "play", "overload-latch"];
+
+fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, anyhow::Error> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("invalid value '{value}' for '--{key}'"))
+}
+
+/// Either one value for both meters or `<l>,<r>`
+fn parse_per_meter<T>(value: &str) -> Result<[T; 2], anyhow::Error>
+where
+    T: FromStr<Err = anyhow::Error> + Copy,
+{
+    Ok(match value.split_once(',') {
+        Some((l, r)) => [l.parse()?, r.parse()?],
+        None => [value.parse()?; 2],
+    })
+}
+
+fn parse_non_negative(key: &str, value: &str) -> Result<f32, anyhow::Error> {
+    let parsed: f32 = parse(key, value)?;
+    if parsed < 0.0 || !parsed.is_finite() {
+        bail!("'--{key}' must not be negative, got '{value}'");
+    }
+    Ok(parsed)
+}
+
+/// Whole numbers that must not be zero, like sample rates and channel counts
+fn parse_count<T: FromStr + Default + PartialEq>(
+    key: &str,
+    value: &str,
+) -> Result<T, anyhow::Error> {
+    let parsed: T = parse(key, value)?;
+    if parsed == T::default() {
+        bail!("'--{key}' must be positive, got '{value}'");
+    }
+    Ok(parsed)
+}
+
+fn parse_positive(key: &str, value: &str) -> Result<f32, anyhow::Error> {
+    let parsed: f32 = parse(key, value)?;
+    if parsed <= 0.0 || !parsed.is_finite() {
+        bail!("'--{key}' must be positive, got '{value}'");
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_zero_counts() {
+        for key in ["rate", "buffer-size", "channels"] {
+            let mut config = Config::default();
+            assert!(config.apply(key, "0").is_err(), "accepted '--{key} 0'");
+            assert!(config.apply(key, "-1").is_err(), "accepted '--{key} -1'");
+            config.apply(key, "2").unwrap();
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Ballistics checked against the IEC 60268-10 (peak programme meter) and
+//! IEC 60268-17 (VU meter) test signals, driving a `Processor` like the audio
+//! thread does.
+
+use std::f32::consts::TAU;
+
+use instant::Instant;
+
+use crate::{
+    ballistics::Ballistics,
+    config::Config,
+    db_to_multiplier,
+    needle::Movement,
+    processor::{Processor, CONTROL_RATE},
+    scales::Deflection,
+};
+
+const SAMPLERATE: usize = 48000;
+/// One control period per buffer, so each buffer leaves exactly one needle position
+const BUF_FRAMES: usize = SAMPLERATE / CONTROL_RATE;
+/// Alignment level, reading 0VU with the default 18dB preamp
+const ALIGNMENT_DBFS: f32 = -18.0;
+
+fn tone(frequency: f32, rms_dbfs: f32, seconds: f32) -> Vec<f32> {
+    let amplitude = db_to_multiplier(rms_dbfs) * 2f32.sqrt();
+    let frames = (seconds * SAMPLERATE as f32).round() as usize;
+    (0..frames)
+        .map(|n| amplitude * (TAU * frequency * n as f32 / SAMPLERATE as f32).sin())
+        .collect()
+}
+
+fn silence(seconds: f32) -> Vec<f32> {
+    vec![0.0; (seconds * SAMPLERATE as f32).round() as usize]
+}
+
+/// The meter as it ships, with the default settings
+fn processor() -> Processor {
+    let mut processor = Processor::new();
+    processor.configure(&Config::default());
+    processor.set_samplerate(SAMPLERATE);
+    processor
+}
+
+/// Feeds a mono signal to both meters, returns the needle every control period
+fn drive(processor: &mut Processor, signal: &[f32]) -> Vec<f32> {
+    signal
+        .chunks(BUF_FRAMES)
+        .map(|chunk| {
+            let buf = chunk.iter().flat_map(|sample| [*sample; 2]).collect();
+            processor.consume_buf(buf);
+            processor.get_needles_for_instant(Instant::now())[0]
+        })
+        .collect()
+}
+
+/// Level a needle position reads on the scale, undoing `Deflection::position`
+fn needle_db(deflection: &Deflection, needle: f32) -> f32 {
+    let range = deflection.negative_db_range + deflection.positive_db_range;
+    needle.powf(1.0 / deflection.bend) * range - deflection.negative_db_range
+}
+
+fn highest(needles: &[f32]) -> f32 {
+    needles.iter().fold(0.0, |max, needle| max.max(*needle))
+}
+
+/// Seconds from the start of `needles` until `reached` holds
+fn time_until(needles: &[f32], reached: impl Fn(f32) -> bool) -> f32 {
+    let idx = needles
+        .iter()
+        .position(|needle| reached(*needle))
+        .expect("never reached");
+    (idx + 1) as f32 / CONTROL_RATE as f32
+}
+
+fn ppm_meter() -> Processor {
+    let mut processor = processor();
+    for channel in 0..2 {
+        processor.set_ballistics(channel, Ballistics::Mechanical(Movement::SifamPpm));
+    }
+    processor
+}
+
+/// IEC 60268-10 Type I: 5kHz tone bursts read this many dB below the steady tone
+#[test]
+fn ppm_tone_bursts_read_within_tolerance() {
+    let mut steady_meter = ppm_meter();
+    let steady_needles = drive(&mut steady_meter, &tone(5000.0, ALIGNMENT_DBFS, 1.0));
+    let steady = needle_db(&steady_meter.deflection, highest(&steady_needles));
+
+    for (burst_ms, expected_db, tolerance_db) in
+        [(10.0, -1.0, 0.5), (5.0, -2.0, 0.5), (3.0, -4.0, 0.5)]
+    {
+        let mut processor = ppm_meter();
+        let burst = tone(5000.0, ALIGNMENT_DBFS, burst_ms / 1000.0);
+        let mut needles = drive(&mut processor, &burst);
+        needles.extend(drive(&mut processor, &silence(0.1)));
+
+        let reading = needle_db(&processor.deflection, highest(&needles)) - steady;
+        assert!(
+            (reading - expected_db).abs() <= tolerance_db,
+            "{burst_ms}ms burst moves the needle to {reading:.2}dB, expected {expected_db}dB"
+        );
+    }
+}
+
+/// IEC 60268-10 Type I: the reading falls back 20dB in 1.7s
+#[test]
+fn ppm_returns_20db_in_1_7_seconds() {
+    let mut processor = ppm_meter();
+    let needles = drive(&mut processor, &tone(5000.0, ALIGNMENT_DBFS, 1.0));
+    let steady = needle_db(&processor.deflection, highest(&needles));
+    let decay = drive(&mut processor, &silence(3.0));
+
+    let deflection = &processor.deflection;
+    let fall_time = time_until(&decay, |needle| {
+        needle_db(deflection, needle) <= steady - 20.0
+    });
+    assert!((fall_time - 1.7).abs() <= 0.3, "20dB in {fall_time:.3}s");
+}
+
+/// IEC 60268-17: a 1kHz tone at reference level reaches 99% of the reference
+/// deflection in 300ms +-10%, overshooting it by 1 to 1.5%
+#[test]
+fn vu_response_time_and_overshoot() {
+    let mut processor = processor();
+    let reference = processor.deflection.position(1.0);
+    let needles = drive(&mut processor, &tone(1000.0, ALIGNMENT_DBFS, 2.0));
+
+    let rise_time = time_until(&needles, |needle| needle >= 0.99 * reference);
+    assert!((rise_time - 0.3).abs() <= 0.03, "99% after {rise_time:.3}s");
+
+    let overshoot = highest(&needles) / reference - 1.0;
+    assert!(
+        (0.01..=0.015).contains(&overshoot),
+        "overshoot {:.2}%",
+        overshoot * 100.0
+    );
+
+    let settled = *needles.last().unwrap();
+    assert!((settled / reference - 1.0).abs() < 0.001);
+}
+
+/// The movement is linear, so the needle returns to rest as fast as it rises
+#[test]
+fn vu_decay_time() {
+    let mut processor = processor();
+    let reference = processor.deflection.position(1.0);
+    drive(&mut processor, &tone(1000.0, ALIGNMENT_DBFS, 2.0));
+    let decay = drive(&mut processor, &silence(2.0));
+
+    let fall_time = time_until(&decay, |needle| needle <= 0.01 * reference);
+    assert!(
+        (fall_time - 0.3).abs() <= 0.03,
+        "back to 1% after {fall_time:.3}s"
+    );
+    assert!(*decay.last().unwrap() < 1e-6);
+}
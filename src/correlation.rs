@@ -0,0 +1,97 @@
+use femtovg::{renderer::OpenGl, Align, Canvas, Color, FontId, Paint, Path};
+
+/// Stereo correlation of the raw L/R input, whatever the metering mode
+pub struct Correlation {
+    time: f32,
+    samplerate: usize,
+    // Exponentially integrated L*R, L*L and R*R
+    sums: [f64; 3],
+}
+
+impl Correlation {
+    /// `time` is the integration time constant in seconds
+    pub fn new(time: f32) -> Self {
+        Self {
+            time,
+            samplerate: 44100,
+            sums: [0.0; 3],
+        }
+    }
+
+    pub fn set_samplerate(&mut self, samplerate: usize) {
+        self.samplerate = samplerate;
+    }
+
+    pub fn consume_buf(&mut self, buf: &[f32]) {
+        let coeff = 1.0 - (-1.0 / (self.time as f64 * self.samplerate as f64)).exp();
+        for pair in buf.chunks_exact(2) {
+            let (l, r) = (pair[0] as f64, pair[1] as f64);
+            for (sum, product) in self.sums.iter_mut().zip([l * r, l * l, r * r]) {
+                *sum += (product - *sum) * coeff;
+            }
+        }
+    }
+
+    /// Correlation in [-1.0, 1.0], 0.0 while either channel is silent
+    pub fn get(&self) -> f32 {
+        let [lr, ll, rr] = self.sums;
+        let energy = (ll * rr).sqrt();
+        if energy < 1e-12 {
+            return 0.0;
+        }
+        (lr / energy).clamp(-1.0, 1.0) as f32
+    }
+}
+
+/// Horizontal -1…+1 correlation scale with a bar and needle at `value`
+pub fn draw_correlation(
+    canvas: &mut Canvas<OpenGl>,
+    font_ids: &[FontId],
+    x: f32,
+    y: f32,
+    w: f32,
+    value: f32,
+) {
+    let center_x = x + w / 2.0;
+    let value_x = |value: f32| center_x + value * w / 2.0;
+
+    // Scale
+    let mut label_paint = Paint::color(Color::rgb(80, 72, 72));
+    label_paint.set_font(&[font_ids[0]]);
+    label_paint.set_font_size(8.0);
+    label_paint.set_text_align(Align::Center);
+    for (mark, label) in [(-1.0, "-1"), (-0.5, ""), (0.0, "0"), (0.5, ""), (1.0, "+1")] {
+        let mut path = Path::new();
+        path.move_to(value_x(mark), y + 8.0);
+        path.line_to(value_x(mark), y + 12.0);
+        let mut paint = Paint::color(Color::rgb(220, 220, 220));
+        paint.set_line_width(1.0);
+        canvas.stroke_path(&path, &paint);
+        canvas
+            .fill_text(value_x(mark), y + 6.0, label, &label_paint)
+            .unwrap();
+    }
+
+    // Bar from center, red when out of phase
+    let bar_color = if value < 0.0 {
+        Color::rgba(220, 62, 73, 160)
+    } else {
+        Color::rgba(255, 48, 0, 100)
+    };
+    let mut path = Path::new();
+    path.rect(
+        center_x.min(value_x(value)),
+        y + 13.0,
+        (value_x(value) - center_x).abs(),
+        4.0,
+    );
+    canvas.fill_path(&path, &Paint::color(bar_color));
+
+    // Needle
+    let mut path = Path::new();
+    path.move_to(value_x(value), y + 10.0);
+    path.line_to(value_x(value), y + 20.0);
+    let mut paint = Paint::color(Color::rgb(255, 200, 160));
+    paint.set_line_width(1.0);
+    canvas.stroke_path(&path, &paint);
+}
@@ -0,0 +1,189 @@
+use std::{f32::consts::PI, str::FromStr};
+
+use anyhow::anyhow;
+
+pub const FFT_SIZES: [usize; 4] = [1024, 2048, 4096, 8192];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowFunction {
+    Rectangular,
+    Hann,
+    BlackmanHarris,
+    FlatTop,
+}
+
+impl WindowFunction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WindowFunction::Rectangular => "RECT",
+            WindowFunction::Hann => "HANN",
+            WindowFunction::BlackmanHarris => "BLACKMAN-HARRIS",
+            WindowFunction::FlatTop => "FLAT-TOP",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            WindowFunction::Rectangular => WindowFunction::Hann,
+            WindowFunction::Hann => WindowFunction::BlackmanHarris,
+            WindowFunction::BlackmanHarris => WindowFunction::FlatTop,
+            WindowFunction::FlatTop => WindowFunction::Rectangular,
+        }
+    }
+
+    pub fn coefficients(&self, len: usize) -> Vec<f32> {
+        // Cosine-sum windows, terms alternate in sign
+        let terms: &[f32] = match self {
+            WindowFunction::Rectangular => &[1.0],
+            WindowFunction::Hann => &[0.5, 0.5],
+            WindowFunction::BlackmanHarris => &[0.35875, 0.48829, 0.14128, 0.01168],
+            WindowFunction::FlatTop => {
+                &[0.21557895, 0.41663158, 0.27726316, 0.08357895, 0.006947368]
+            }
+        };
+
+        (0..len)
+            .map(|n| {
+                let phase = 2.0 * PI * n as f32 / len as f32;
+                terms
+                    .iter()
+                    .enumerate()
+                    .map(|(k, a)| {
+                        let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+                        sign * a * (phase * k as f32).cos()
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+impl FromStr for WindowFunction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rect" | "rectangular" => Ok(WindowFunction::Rectangular),
+            "hann" => Ok(WindowFunction::Hann),
+            "blackman-harris" => Ok(WindowFunction::BlackmanHarris),
+            "flat-top" => Ok(WindowFunction::FlatTop),
+            _ => Err(anyhow!("unknown window function '{s}'")),
+        }
+    }
+}
+
+/// In-place iterative radix-2 FFT, `re.len()` must be a power of two
+pub fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    debug_assert!(n.is_power_of_two() && im.len() == n);
+
+    // Bit reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        for k in 0..len / 2 {
+            let (w_im, w_re) = (-2.0 * PI * k as f32 / len as f32).sin_cos();
+            for start in (0..n).step_by(len) {
+                let a = start + k;
+                let b = a + len / 2;
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const N: usize = 1024;
+
+    fn spectrum(signal: &[f32]) -> (Vec<f32>, Vec<f32>) {
+        let mut re = signal.to_vec();
+        let mut im = vec![0.0; signal.len()];
+        fft(&mut re, &mut im);
+        (re, im)
+    }
+
+    fn magnitudes(signal: &[f32]) -> Vec<f32> {
+        let (re, im) = spectrum(signal);
+        re.iter().zip(&im).map(|(re, im)| re.hypot(*im)).collect()
+    }
+
+    #[test]
+    fn impulse_has_a_flat_spectrum() {
+        let mut impulse = vec![0.0; N];
+        impulse[0] = 1.0;
+        for magnitude in magnitudes(&impulse) {
+            assert!((magnitude - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn bin_centred_sine_lands_in_one_bin() {
+        let bin = 37;
+        let sine: Vec<f32> = (0..N)
+            .map(|n| (2.0 * PI * (bin * n) as f32 / N as f32).sin())
+            .collect();
+        for (k, magnitude) in magnitudes(&sine).iter().enumerate() {
+            if k == bin || k == N - bin {
+                assert!((magnitude - N as f32 / 2.0).abs() < 1e-2);
+            } else {
+                assert!(*magnitude < 1e-2, "{magnitude} in bin {k}");
+            }
+        }
+    }
+
+    #[test]
+    fn energy_is_preserved() {
+        // Parseval: sum |x|^2 = sum |X|^2 / N
+        let mut rng = 1u32;
+        let noise: Vec<f32> = (0..N)
+            .map(|_| {
+                rng = rng.wrapping_mul(1664525).wrapping_add(1013904223);
+                rng as f32 / u32::MAX as f32 - 0.5
+            })
+            .collect();
+        let time: f32 = noise.iter().map(|x| x * x).sum();
+        let (re, im) = spectrum(&noise);
+        let frequency: f32 = re.iter().zip(&im).map(|(re, im)| re * re + im * im).sum();
+        assert!((frequency / N as f32 / time - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn windows_have_their_coherent_gains() {
+        for (window, gain) in [
+            (WindowFunction::Rectangular, 1.0),
+            (WindowFunction::Hann, 0.5),
+            (WindowFunction::BlackmanHarris, 0.35875),
+            (WindowFunction::FlatTop, 0.21557895),
+        ] {
+            let coefficients = window.coefficients(N);
+            let coherent_gain = coefficients.iter().sum::<f32>() / N as f32;
+            assert!(
+                (coherent_gain - gain).abs() < 1e-5,
+                "{} gain {coherent_gain}",
+                window.name()
+            );
+        }
+    }
+}
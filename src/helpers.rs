@@ -1,7 +1,7 @@
 use std::{num::NonZeroU32, sync::mpsc::Receiver};
 use femtovg::{Align, Baseline, Canvas, Color, Paint, Path, Renderer};
 
-use crate::AudioEvent;
+use crate::{config::Config, AudioEvent};
 
 use super::run;
 
@@ -23,6 +23,7 @@ pub fn start(
     title: &'static str,
     resizeable: bool,
     rx: Receiver<AudioEvent>,
+    config: Config,
 ) {
     // This provides better error messages in debug mode.
     // It's disabled in release mode so it doesn't bloat up the file size.
@@ -114,6 +115,7 @@ pub fn start(
         surface,
         window,
         rx,
+        config,
     );
 }
 
@@ -138,6 +140,7 @@ impl PerfGraph {
         self.values[self.head] = frame_time;
     }
 
+    #[allow(unused)]
     pub fn get_average(&self) -> f32 {
         self.values.iter().sum::<f32>() / self.history_count as f32
     }
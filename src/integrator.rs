@@ -0,0 +1,283 @@
+use std::{collections::VecDeque, str::FromStr};
+
+use anyhow::anyhow;
+
+pub const INTEGRATION_PRESETS: [f32; 3] = [0.05, 0.3, 3.0];
+
+/// The running window sum is recomputed from scratch this often, so rounding
+/// errors from adding and subtracting squares never accumulate
+const RESUM_INTERVAL: usize = 1 << 16;
+
+/// How the RMS detector averages squared samples, times in seconds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Integration {
+    /// Moving average over a fixed window
+    Rectangular(f32),
+    /// RC-style exponential average with the given time constant
+    Exponential(f32),
+}
+
+impl Integration {
+    pub fn seconds(&self) -> f32 {
+        match self {
+            Integration::Rectangular(seconds) | Integration::Exponential(seconds) => *seconds,
+        }
+    }
+
+    pub fn with_seconds(&self, seconds: f32) -> Self {
+        match self {
+            Integration::Rectangular(_) => Integration::Rectangular(seconds),
+            Integration::Exponential(_) => Integration::Exponential(seconds),
+        }
+    }
+
+    pub fn toggle_kind(&self) -> Self {
+        match self {
+            Integration::Rectangular(seconds) => Integration::Exponential(*seconds),
+            Integration::Exponential(seconds) => Integration::Rectangular(*seconds),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        let kind = match self {
+            Integration::Rectangular(_) => "RECT",
+            Integration::Exponential(_) => "RC",
+        };
+        let seconds = self.seconds();
+        if seconds < 1.0 {
+            format!("{kind} {:.0}ms", seconds * 1000.0)
+        } else {
+            format!("{kind} {seconds:.1}s")
+        }
+    }
+}
+
+/// Parses `[rect:|rc:]<time>` where time is seconds, or milliseconds with an `ms` suffix
+impl FromStr for Integration {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, time) = s.split_once(':').unwrap_or(("rect", s));
+        let seconds = match time.strip_suffix("ms") {
+            Some(ms) => ms.parse::<f32>().map(|ms| ms / 1000.0),
+            None => time.trim_end_matches('s').parse::<f32>(),
+        }
+        .map_err(|_| anyhow!("invalid integration time '{time}'"))?;
+        if seconds <= 0.0 || !seconds.is_finite() {
+            return Err(anyhow!("integration time must be positive, got '{time}'"));
+        }
+
+        match kind {
+            "rect" => Ok(Integration::Rectangular(seconds)),
+            "rc" => Ok(Integration::Exponential(seconds)),
+            _ => Err(anyhow!("unknown integrator '{kind}'")),
+        }
+    }
+}
+
+/// Mean-square detector for a single channel
+pub struct Integrator {
+    integration: Integration,
+    samplerate: usize,
+    squares: VecDeque<f64>,
+    square_sum: f64,
+    since_resum: usize,
+    mean_square: f64,
+}
+
+impl Integrator {
+    pub fn new(integration: Integration, samplerate: usize) -> Self {
+        Self {
+            integration,
+            samplerate,
+            squares: VecDeque::new(),
+            square_sum: 0.0,
+            since_resum: 0,
+            mean_square: 0.0,
+        }
+    }
+
+    pub fn integration(&self) -> Integration {
+        self.integration
+    }
+
+    /// Switches integration at runtime, keeping the current reading. Only a grown
+    /// rectangular window dips, reading as if preceded by silence until it fills.
+    pub fn set_integration(&mut self, integration: Integration) {
+        let mean_square = self.mean_square();
+        let was_rectangular = matches!(self.integration, Integration::Rectangular(_));
+        self.integration = integration;
+
+        match integration {
+            Integration::Rectangular(_) if was_rectangular => self.trim(),
+            Integration::Rectangular(_) => {
+                // A window full of the current mean square reads the same
+                let window_len = self.window_len();
+                self.squares = std::iter::repeat_n(mean_square, window_len).collect();
+                self.square_sum = mean_square * window_len as f64;
+                self.since_resum = 0;
+            }
+            Integration::Exponential(_) => {
+                self.squares.clear();
+                self.square_sum = 0.0;
+                self.mean_square = mean_square;
+            }
+        }
+    }
+
+    pub fn set_samplerate(&mut self, samplerate: usize) {
+        self.samplerate = samplerate;
+        self.trim();
+    }
+
+    fn window_len(&self) -> usize {
+        ((self.samplerate as f32 * self.integration.seconds()) as usize).max(1)
+    }
+
+    /// Drops squares that fell out of a shrunk window
+    fn trim(&mut self) {
+        let window_len = self.window_len();
+        while self.squares.len() > window_len {
+            self.square_sum -= self.squares.pop_front().unwrap();
+        }
+    }
+
+    /// Feeds one sample in, returns the mean square after it
+    pub fn push(&mut self, sample: f32) -> f64 {
+        match self.integration {
+            Integration::Rectangular(_) => {
+                let square = (sample as f64).powi(2);
+                self.squares.push_back(square);
+                self.square_sum += square;
+                self.trim();
+
+                self.since_resum += 1;
+                if self.since_resum >= RESUM_INTERVAL {
+                    self.square_sum = self.squares.iter().sum();
+                    self.since_resum = 0;
+                }
+            }
+            Integration::Exponential(seconds) => {
+                let coeff = 1.0 - (-1.0 / (seconds as f64 * self.samplerate as f64)).exp();
+                let square = (sample as f64).powi(2);
+                self.mean_square += (square - self.mean_square) * coeff;
+            }
+        }
+        self.mean_square()
+    }
+
+    pub fn mean_square(&self) -> f64 {
+        match self.integration {
+            Integration::Rectangular(_) => {
+                // Window not yet full reads as if preceded by silence. Clamped since
+                // rounding can leave a tiny negative sum right after loud material.
+                (self.square_sum / self.window_len() as f64).max(0.0)
+            }
+            Integration::Exponential(_) => self.mean_square,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+
+    const SAMPLERATE: usize = 48000;
+
+    fn sine(db: f64, seconds: f64) -> impl Iterator<Item = f32> {
+        let amplitude = 10.0f64.powf(db / 20.0);
+        let len = (SAMPLERATE as f64 * seconds) as usize;
+        (0..len).map(move |n| {
+            (amplitude * (2.0 * PI * 997.0 * n as f64 / SAMPLERATE as f64).sin()) as f32
+        })
+    }
+
+    fn rms_db(integrator: &Integrator) -> f64 {
+        10.0 * integrator.mean_square().log10()
+    }
+
+    #[test]
+    fn sine_reads_accurately_down_to_minus_120() {
+        for integration in [Integration::Rectangular(0.3), Integration::Exponential(0.3)] {
+            for db in [0.0, -20.0, -60.0, -84.0, -100.0, -120.0] {
+                let mut integrator = Integrator::new(integration, SAMPLERATE);
+                for sample in sine(db, 3.0) {
+                    integrator.push(sample);
+                }
+                // Sine RMS is 3.01dB below its peak
+                let expected = db - 10.0 * 2.0f64.log10();
+                let error = rms_db(&integrator) - expected;
+                assert!(
+                    error.abs() < 0.05,
+                    "{integration:?} at {db}dBFS reads {:.3}dB off",
+                    error
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn quiet_signal_after_loud_material_does_not_drift() {
+        let mut integrator = Integrator::new(Integration::Rectangular(0.3), SAMPLERATE);
+        // Several resummation intervals of full scale noise-like material
+        for n in 0..SAMPLERATE * 5 {
+            integrator.push(if n % 7 < 3 { 1.0 } else { -0.9 });
+        }
+        for sample in sine(-120.0, 2.0) {
+            integrator.push(sample);
+        }
+        let error = rms_db(&integrator) - (-120.0 - 10.0 * 2.0f64.log10());
+        assert!(error.abs() < 0.05, "reads {error:.3}dB off");
+    }
+
+    #[test]
+    fn shrinking_window_at_runtime_keeps_sum_consistent() {
+        let mut integrator = Integrator::new(Integration::Rectangular(3.0), SAMPLERATE);
+        for sample in sine(-40.0, 4.0) {
+            integrator.push(sample);
+        }
+        integrator.set_integration(Integration::Rectangular(0.05));
+        let expected = -40.0 - 10.0 * 2.0f64.log10();
+        assert!((rms_db(&integrator) - expected).abs() < 0.05);
+
+        integrator.set_integration(Integration::Rectangular(3.0));
+        for sample in sine(-40.0, 4.0) {
+            integrator.push(sample);
+        }
+        assert!((rms_db(&integrator) - expected).abs() < 0.05);
+    }
+
+    #[test]
+    fn switching_integrator_kind_keeps_the_reading() {
+        let expected = -20.0 - 10.0 * 2.0f64.log10();
+        let mut integrator = Integrator::new(Integration::Exponential(0.3), SAMPLERATE);
+        for sample in sine(-20.0, 3.0) {
+            integrator.push(sample);
+        }
+
+        integrator.set_integration(Integration::Rectangular(0.3));
+        assert!((rms_db(&integrator) - expected).abs() < 0.05);
+        // The seeded window ages out into the real signal without a jump
+        for sample in sine(-20.0, 0.5) {
+            integrator.push(sample);
+            assert!((rms_db(&integrator) - expected).abs() < 0.1);
+        }
+
+        integrator.set_integration(Integration::Exponential(0.3));
+        assert!((rms_db(&integrator) - expected).abs() < 0.05);
+    }
+
+    #[test]
+    fn silence_reads_zero() {
+        let mut integrator = Integrator::new(Integration::Rectangular(0.3), SAMPLERATE);
+        for sample in sine(0.0, 1.0) {
+            integrator.push(sample);
+        }
+        for _ in 0..SAMPLERATE {
+            integrator.push(0.0);
+        }
+        assert!(integrator.mean_square() < 1e-20);
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::VecDeque;
+
+use biquad::DirectForm2Transposed;
+
+use crate::weighting::{run_filters, Weighting};
+
+/// Loudness is measured over blocks this long
+const BLOCK_SECONDS: f64 = 0.1;
+/// 400ms momentary window
+const MOMENTARY_BLOCKS: usize = 4;
+/// 3s short-term window
+const SHORT_TERM_BLOCKS: usize = 30;
+
+/// ITU-R BS.1770 loudness of a stereo programme, momentary and short-term as in
+/// EBU R 128, in LUFS
+pub struct Loudness {
+    samplerate: usize,
+    filters: [Vec<DirectForm2Transposed<f64>>; 2],
+    /// K-weighted squares of the block being filled, summed over both channels
+    block_sum: f64,
+    block_frames: usize,
+    /// Mean squares of the latest finished blocks, oldest first
+    blocks: VecDeque<f64>,
+}
+
+impl Loudness {
+    pub fn new() -> Self {
+        let mut loudness = Self {
+            samplerate: 44100,
+            filters: Default::default(),
+            block_sum: 0.0,
+            block_frames: 0,
+            blocks: VecDeque::with_capacity(SHORT_TERM_BLOCKS),
+        };
+        loudness.set_samplerate(44100);
+        loudness
+    }
+
+    pub fn set_samplerate(&mut self, samplerate: usize) {
+        self.samplerate = samplerate;
+        self.filters = std::array::from_fn(|_| Weighting::K.filters(samplerate));
+        self.reset();
+    }
+
+    /// Starts both windows over
+    pub fn reset(&mut self) {
+        self.block_sum = 0.0;
+        self.block_frames = 0;
+        self.blocks.clear();
+    }
+
+    /// Takes interleaved L/R pairs
+    pub fn consume_buf(&mut self, buf: &[f32]) {
+        let frames_per_block = (self.samplerate as f64 * BLOCK_SECONDS).round() as usize;
+        for pair in buf.chunks_exact(2) {
+            for (filters, sample) in self.filters.iter_mut().zip(pair) {
+                let weighted = run_filters(filters, *sample as f64);
+                self.block_sum += weighted * weighted;
+            }
+            self.block_frames += 1;
+
+            if self.block_frames == frames_per_block {
+                if self.blocks.len() == SHORT_TERM_BLOCKS {
+                    self.blocks.pop_front();
+                }
+                self.blocks
+                    .push_back(self.block_sum / frames_per_block as f64);
+                self.block_sum = 0.0;
+                self.block_frames = 0;
+            }
+        }
+    }
+
+    /// Over the last 400ms, None until that much has been metered
+    pub fn momentary(&self) -> Option<f32> {
+        self.over(MOMENTARY_BLOCKS)
+    }
+
+    /// Over the last 3s, None until that much has been metered
+    pub fn short_term(&self) -> Option<f32> {
+        self.over(SHORT_TERM_BLOCKS)
+    }
+
+    /// Loudness of the latest `blocks`, -inf for silence
+    fn over(&self, blocks: usize) -> Option<f32> {
+        if self.blocks.len() < blocks {
+            return None;
+        }
+        let mean_square = self.blocks.iter().rev().take(blocks).sum::<f64>() / blocks as f64;
+        Some((-0.691 + 10.0 * mean_square.log10()) as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+    use crate::db_to_multiplier;
+
+    const SAMPLERATE: usize = 48000;
+
+    /// `seconds` of a 997Hz sine peaking at `peak_db` on each channel, None for silence
+    fn sine(peak_db: [Option<f32>; 2], seconds: f64) -> Vec<f32> {
+        let amplitudes = peak_db.map(|db| db.map_or(0.0, |db| db_to_multiplier(db) as f64));
+        (0..(seconds * SAMPLERATE as f64) as usize)
+            .flat_map(|n| {
+                let s = (2.0 * PI * 997.0 * n as f64 / SAMPLERATE as f64).sin();
+                amplitudes.map(|amplitude| (amplitude * s) as f32)
+            })
+            .collect()
+    }
+
+    fn loudness() -> Loudness {
+        let mut loudness = Loudness::new();
+        loudness.set_samplerate(SAMPLERATE);
+        loudness
+    }
+
+    /// BS.1770: a 0dBFS 997Hz sine on one channel reads -3.01LKFS
+    #[test]
+    fn full_scale_sine_on_one_channel_reads_minus_3_lufs() {
+        let mut loudness = loudness();
+        loudness.consume_buf(&sine([Some(0.0), None], 4.0));
+        for reading in [loudness.momentary(), loudness.short_term()] {
+            let reading = reading.unwrap();
+            assert!((reading + 3.01).abs() < 0.02, "reads {reading:.2}LUFS");
+        }
+    }
+
+    /// EBU R 128 reference: a -23dBFS sine on both channels reads -23LUFS
+    #[test]
+    fn reference_tone_reads_minus_23_lufs() {
+        let mut loudness = loudness();
+        loudness.consume_buf(&sine([Some(-23.0); 2], 4.0));
+        let momentary = loudness.momentary().unwrap();
+        assert!((momentary + 23.0).abs() < 0.02, "reads {momentary:.2}LUFS");
+    }
+
+    #[test]
+    fn windows_fill_before_reading() {
+        let mut loudness = loudness();
+        loudness.consume_buf(&sine([Some(-20.0); 2], 0.35));
+        assert_eq!(loudness.momentary(), None);
+        loudness.consume_buf(&sine([Some(-20.0); 2], 0.1));
+        assert!(loudness.momentary().is_some());
+        assert_eq!(loudness.short_term(), None);
+        loudness.consume_buf(&sine([Some(-20.0); 2], 2.6));
+        assert!(loudness.short_term().is_some());
+
+        loudness.reset();
+        assert_eq!(loudness.momentary(), None);
+        assert_eq!(loudness.short_term(), None);
+    }
+
+    /// Momentary follows a drop in level within its 400ms, short-term lags behind
+    #[test]
+    fn momentary_follows_faster_than_short_term() {
+        let mut loudness = loudness();
+        loudness.consume_buf(&sine([Some(-10.0); 2], 3.0));
+        loudness.consume_buf(&sine([None; 2], 0.5));
+        let momentary = loudness.momentary().unwrap();
+        assert!(momentary < -100.0, "reads {momentary:.2}LUFS");
+        let short_term = loudness.short_term().unwrap();
+        assert!(
+            (-11.0..-10.0).contains(&short_term),
+            "reads {short_term:.2}LUFS"
+        );
+    }
+}
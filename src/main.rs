@@ -1,21 +1,33 @@
 use std::sync::mpsc::Receiver;
 
 use audio::audio_thread;
-use biquad::{Biquad, Coefficients, DirectForm2Transposed, ToHertz, Q_BUTTERWORTH_F32};
+use clip_log::draw_clip_log_overlay;
+use config::Config;
+use correlation::{draw_correlation, Correlation};
 use femtovg::{renderer::OpenGl, Align, Canvas, Color, FontId, Paint, Path};
 use glutin::{
     context::PossiblyCurrentContext,
     surface::{Surface, WindowSurface},
 };
 use instant::Instant;
+use integrator::INTEGRATION_PRESETS;
 use log::info;
+use loudness::Loudness;
+use osc::{Osc, OscCommand};
+use overload::{lamp_hit, LAMP_ATTACK, LAMP_RELEASE, LAMP_Y};
+use panels::{Goniometer, History, Levels, Panel, Spectrum};
 use processor::Processor;
+use readouts::{draw_readouts, readouts_hit, MeterReadouts};
 use resource::resource;
+use routing::{draw_routing_overlay, routing_cell_at, Routing};
 use scales::{draw_scale, generate_din_scale, Mark};
+use stereo_mode::StereoMode;
+use telemetry::Telemetry;
 use usvg::{
     tiny_skia_path::{PathSegment, Point},
     Node,
 };
+use weighting::Weighting;
 use winit::{
     application::ApplicationHandler,
     event::{ElementState, MouseButton, WindowEvent},
@@ -24,32 +36,92 @@ use winit::{
     window::Window,
 };
 
-const MOTION_FILTER_CUTOFF: f32 = 4.0;
-
 mod audio;
+mod ballistics;
+mod clip_log;
+mod config;
+#[cfg(test)]
+mod conformance;
+mod correlation;
+mod fft;
 mod helpers;
-mod motion_filter;
+mod integrator;
+mod loudness;
+mod needle;
+mod osc;
+mod overload;
+mod panels;
 mod processor;
+mod readouts;
+mod routing;
 mod scales;
+mod stereo_mode;
+mod telemetry;
+mod weighting;
 
 use helpers::PerfGraph;
 
 enum AudioEvent {
-    Config { samplerate: usize },
-    Buffer { buf: Vec<f32> },
+    Config {
+        samplerate: usize,
+        channels: usize,
+        /// Whether frames are a position in the input, as for piped PCM
+        positioned: bool,
+    },
+    Buffer {
+        buf: Vec<f32>,
+    },
+    /// Metered while the input is gone, not part of it
+    Silence {
+        buf: Vec<f32>,
+    },
+    Lost {
+        reason: String,
+    },
+    PacketLoss {
+        lost: u64,
+    },
 }
 
 const VU_WIDTH: f32 = 320.0;
+const VU_HEIGHT: f32 = 220.0;
+const CORRELATION_HEIGHT: f32 = 24.0;
+const PANEL_HEIGHT: f32 = 160.0;
 
 fn main() {
     pretty_env_logger::init();
     info!("Hi");
+    let config = match Config::from_args() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(2);
+        }
+    };
+    if config.list_devices {
+        if let Err(err) = audio::list_devices() {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    audio::set_monitor_env(&config.audio);
+
     let (tx, rx) = std::sync::mpsc::channel();
     let (shutdown_tx, shutdown_rx) = std::sync::mpsc::channel();
-    std::thread::spawn(|| audio_thread(tx, shutdown_rx));
-    helpers::start((VU_WIDTH * 2.0) as u32 * 2, 220 * 2, "VU", true, rx);
+    let audio_config = config.audio.clone();
+    std::thread::spawn(move || audio_thread(tx, shutdown_rx, audio_config));
+    helpers::start(
+        (VU_WIDTH * 2.0) as u32 * 2,
+        (VU_HEIGHT + CORRELATION_HEIGHT + PANEL_HEIGHT) as u32 * 2,
+        "VU",
+        true,
+        rx,
+        config,
+    );
 
-    shutdown_tx.send(()).unwrap();
+    // The audio thread may already have ended, with the UI gone
+    let _ = shutdown_tx.send(());
 }
 
 use glutin::prelude::*;
@@ -68,13 +140,29 @@ struct App {
     processor: Processor,
     last_hand_pos: [(f32, f32); 2],
     last_last_hand_pos: [(f32, f32); 2],
-    negative_db_range: f32,
-    bend: f32,
     font_ids: Vec<FontId>,
     marks: Vec<Mark>,
     overload: [f32; 2],
-    filter: [DirectForm2Transposed<f32>; 2],
-    last_fps: u32,
+    overload_latch: bool,
+    /// Latched overload lamps, lit until clicked
+    latched: [bool; 2],
+    panels: Vec<Box<dyn Panel>>,
+    active_panel: usize,
+    correlation: Correlation,
+    /// BS.1770 loudness of the routed L/R input, whatever the metering mode
+    loudness: Loudness,
+    stereo_mode: StereoMode,
+    routing: Routing,
+    input_channels: usize,
+    routing_overlay: bool,
+    clip_log_overlay: bool,
+    clip_log_path: String,
+    /// Why audio input is gone, while it is
+    audio_lost: Option<String>,
+    /// Packets lost by a network source so far, and when the count last went up
+    packet_loss: Option<(u64, Instant)>,
+    osc: Option<Osc>,
+    telemetry: Option<Telemetry>,
 }
 
 impl ApplicationHandler for App {
@@ -82,6 +170,13 @@ impl ApplicationHandler for App {
         info!("resumed... and what?")
     }
 
+    /// Keeps metering, remote control and publishing going while nothing is drawn,
+    /// such as when the window is minimised
+    fn about_to_wait(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {
+        self.receive();
+        self.publish();
+    }
+
     fn window_event(
         &mut self,
         event_loop: &winit::event_loop::ActiveEventLoop,
@@ -91,16 +186,7 @@ impl ApplicationHandler for App {
         // info!("{:?}", event);
         event_loop.set_control_flow(ControlFlow::Poll);
 
-        while let Ok(data) = self.rx.try_recv() {
-            match data {
-                AudioEvent::Config { samplerate } => {
-                    self.processor.set_samplerate(samplerate);
-                }
-                AudioEvent::Buffer { buf } => {
-                    self.processor.consume_buf(buf);
-                }
-            }
-        }
+        self.receive();
 
         match event {
             WindowEvent::KeyboardInput { event, .. } => {
@@ -111,20 +197,24 @@ impl ApplicationHandler for App {
                     let step = 0.1;
                     match key_code {
                         KeyCode::Equal => {
-                            self.negative_db_range += step;
-                            info!("negative_db_range: {}", self.negative_db_range);
+                            let deflection = &mut self.processor.deflection;
+                            deflection.negative_db_range += step;
+                            info!("negative_db_range: {}", deflection.negative_db_range);
                         }
                         KeyCode::Minus => {
-                            self.negative_db_range -= step;
-                            info!("negative_db_range: {}", self.negative_db_range);
+                            let deflection = &mut self.processor.deflection;
+                            deflection.negative_db_range -= step;
+                            info!("negative_db_range: {}", deflection.negative_db_range);
                         }
                         KeyCode::BracketLeft => {
-                            self.bend -= step;
-                            info!("bend: {}", self.bend);
+                            let deflection = &mut self.processor.deflection;
+                            deflection.bend -= step;
+                            info!("bend: {}", deflection.bend);
                         }
                         KeyCode::BracketRight => {
-                            self.bend += step;
-                            info!("bend: {}", self.bend);
+                            let deflection = &mut self.processor.deflection;
+                            deflection.bend += step;
+                            info!("bend: {}", deflection.bend);
                         }
                         KeyCode::KeyZ => {
                             let preamp = multiplier_to_db(self.processor.preamp);
@@ -138,7 +228,61 @@ impl ApplicationHandler for App {
                             self.processor.preamp = db_to_multiplier(db);
                             info!("preamp: {}", db);
                         }
-                        _ => {}
+                        KeyCode::KeyM => {
+                            self.stereo_mode = self.stereo_mode.next();
+                            info!("stereo mode: {:?}", self.stereo_mode);
+                        }
+                        KeyCode::Digit1 | KeyCode::Digit2 => {
+                            let channel = if key_code == KeyCode::Digit1 { 0 } else { 1 };
+                            let weighting = self.processor.weighting(channel).next();
+                            self.processor.set_weighting(channel, weighting);
+                            info!("weighting {}: {:?}", channel, weighting);
+                        }
+                        KeyCode::KeyI => {
+                            for channel in 0..2 {
+                                let integration = self.processor.integration(channel);
+                                let idx = INTEGRATION_PRESETS
+                                    .iter()
+                                    .position(|seconds| *seconds == integration.seconds());
+                                let seconds = INTEGRATION_PRESETS
+                                    [idx.map_or(0, |idx| (idx + 1) % INTEGRATION_PRESETS.len())];
+                                let integration = integration.with_seconds(seconds);
+                                self.processor.set_integration(channel, integration);
+                                info!("integration {}: {:?}", channel, integration);
+                            }
+                        }
+                        KeyCode::KeyO => {
+                            for channel in 0..2 {
+                                let integration = self.processor.integration(channel).toggle_kind();
+                                self.processor.set_integration(channel, integration);
+                                info!("integration {}: {:?}", channel, integration);
+                            }
+                        }
+                        KeyCode::KeyN => {
+                            for channel in 0..2 {
+                                let ballistics = self.processor.ballistics(channel).next();
+                                self.processor.set_ballistics(channel, ballistics);
+                                info!("ballistics {}: {}", channel, ballistics.name());
+                            }
+                        }
+                        KeyCode::KeyR => {
+                            self.routing_overlay = !self.routing_overlay;
+                        }
+                        KeyCode::KeyL => {
+                            self.clip_log_overlay = !self.clip_log_overlay;
+                        }
+                        KeyCode::KeyE => {
+                            let csv = self.processor.clip_log().to_csv();
+                            match std::fs::write(&self.clip_log_path, csv) {
+                                Ok(()) => info!("clip log exported to {}", self.clip_log_path),
+                                Err(err) => eprintln!("cannot export clip log: {err}"),
+                            }
+                        }
+                        KeyCode::Tab => {
+                            self.active_panel = (self.active_panel + 1) % self.panels.len();
+                            info!("panel: {}", self.panels[self.active_panel].name());
+                        }
+                        key_code => self.panels[self.active_panel].key(key_code),
                     }
                 }
             }
@@ -154,7 +298,31 @@ impl ApplicationHandler for App {
                 state,
                 ..
             } => match state {
-                ElementState::Pressed => self.dragging = true,
+                ElementState::Pressed => {
+                    let pt = self
+                        .canvas
+                        .transform()
+                        .inversed()
+                        .transform_point(self.mouse.0, self.mouse.1);
+                    let cell =
+                        routing_cell_at(self.input_channels, pt).filter(|_| self.routing_overlay);
+                    let hit = (0..2)
+                        .find(|idx| readouts_hit(VU_WIDTH * *idx as f32 + VU_WIDTH / 2.0, pt));
+                    let lamp =
+                        (0..2).find(|idx| lamp_hit(VU_WIDTH * *idx as f32 + VU_WIDTH / 2.0, pt));
+                    if let Some((meter, channel)) = cell {
+                        self.routing.toggle(meter, channel);
+                        info!("routing: {:?}", self.routing);
+                    } else if let Some(idx) = lamp {
+                        self.latched[idx] = false;
+                        info!("cleared overload lamp for meter {}", idx);
+                    } else if let Some(idx) = hit {
+                        self.processor.reset_stats(idx);
+                        info!("reset stats for meter {}", idx);
+                    } else {
+                        self.dragging = true;
+                    }
+                }
                 ElementState::Released => self.dragging = false,
             },
             WindowEvent::CursorMoved {
@@ -172,13 +340,14 @@ impl ApplicationHandler for App {
                     let dx = position.x as f32 - self.mouse.0;
                     let dy = position.y as f32 - self.mouse.1;
 
+                    let deflection = &mut self.processor.deflection;
                     if dx.abs() > dy.abs() {
-                        self.negative_db_range += dx * 0.1;
-                        self.negative_db_range = self.negative_db_range.min(100.0);
-                        info!("negative_db_range: {}", self.negative_db_range);
+                        deflection.negative_db_range += dx * 0.1;
+                        deflection.negative_db_range = deflection.negative_db_range.min(100.0);
+                        info!("negative_db_range: {}", deflection.negative_db_range);
                     } else {
-                        self.bend *= 1.0 + dy * 0.003;
-                        info!("bend: {}", self.bend);
+                        deflection.bend *= 1.0 + dy * 0.003;
+                        info!("bend: {}", deflection.bend);
                     }
                 }
 
@@ -195,6 +364,13 @@ impl ApplicationHandler for App {
                     .transform()
                     .inversed()
                     .transform_point(self.mouse.0, self.mouse.1);
+                if let Some((meter, channel)) =
+                    routing_cell_at(self.input_channels, pt).filter(|_| self.routing_overlay)
+                {
+                    self.routing.adjust_gain(meter, channel, y);
+                    info!("routing: {:?}", self.routing);
+                    return;
+                }
                 self.canvas.translate(pt.0, pt.1);
                 self.canvas.scale(1.0 + (y / 10.0), 1.0 + (y / 10.0));
                 self.canvas.translate(-pt.0, -pt.1);
@@ -240,6 +416,70 @@ impl ApplicationHandler for App {
                         .unwrap();
                 }
 
+                // Correlation
+                draw_correlation(
+                    &mut self.canvas,
+                    &self.font_ids,
+                    VU_WIDTH / 2.0,
+                    VU_HEIGHT,
+                    VU_WIDTH,
+                    self.correlation.get(),
+                );
+
+                // Panels
+                let preamp = self.processor.preamp;
+                let peaks = self.processor.get_peaks().map(|peak| peak * preamp);
+                let levels = Levels { rms, peak: peaks };
+                for panel in &mut self.panels {
+                    panel.update(&levels, dt);
+                }
+                {
+                    let panel = &self.panels[self.active_panel];
+                    let panel_y = VU_HEIGHT + CORRELATION_HEIGHT;
+                    paint.set_text_align(Align::Right);
+                    paint.set_font_size(8.0);
+                    self.canvas
+                        .fill_text(VU_WIDTH * 2.0 - 4.0, panel_y + 10.0, panel.name(), &paint)
+                        .unwrap();
+                    panel.draw(
+                        &mut self.canvas,
+                        &self.font_ids,
+                        0.0,
+                        panel_y,
+                        VU_WIDTH * 2.0,
+                        PANEL_HEIGHT,
+                    );
+                }
+
+                // Meter labels
+                paint.set_text_align(Align::Center);
+                paint.set_font(&[self.font_ids[1]]);
+                paint.set_font_size(12.0);
+                for (idx, label) in self.stereo_mode.labels().into_iter().enumerate() {
+                    let weighting = self.processor.weighting(idx);
+                    let label = match weighting {
+                        Weighting::Z => label.to_string(),
+                        _ => format!("{label} ({})", weighting.name()),
+                    };
+                    let x = VU_WIDTH * idx as f32 + VU_WIDTH / 2.0;
+                    paint.set_font_size(12.0);
+                    self.canvas.fill_text(x, 150.0, label, &paint).unwrap();
+                    paint.set_font_size(8.0);
+                    self.canvas
+                        .fill_text(x, 160.0, self.processor.integration(idx).name(), &paint)
+                        .unwrap();
+                }
+
+                // Readouts
+                for (idx, readouts) in self.readouts(rms).iter().enumerate() {
+                    draw_readouts(
+                        &mut self.canvas,
+                        &self.font_ids,
+                        VU_WIDTH * idx as f32 + VU_WIDTH / 2.0,
+                        readouts,
+                    );
+                }
+
                 // Filters
                 // paint.set_text_align(Align::Left);
                 // let lines = format!("{:#?}", self.filter);
@@ -254,6 +494,7 @@ impl ApplicationHandler for App {
 
                 // Scales
                 {
+                    let deflection = self.processor.deflection;
                     self.canvas.save();
                     self.canvas.translate(VU_WIDTH / 2.0, center_y);
                     draw_scale(
@@ -261,9 +502,9 @@ impl ApplicationHandler for App {
                         self.font_ids[0],
                         &self.marks,
                         MAX_ANGLE,
-                        self.negative_db_range,
-                        6.0,
-                        self.bend,
+                        deflection.negative_db_range,
+                        deflection.positive_db_range,
+                        deflection.bend,
                     );
                     self.canvas.translate(VU_WIDTH, 0.0);
                     draw_scale(
@@ -271,39 +512,40 @@ impl ApplicationHandler for App {
                         self.font_ids[0],
                         &self.marks,
                         MAX_ANGLE,
-                        self.negative_db_range,
-                        6.0,
-                        self.bend,
+                        deflection.negative_db_range,
+                        deflection.positive_db_range,
+                        deflection.bend,
                     );
                     self.canvas.restore();
                 }
 
                 {
                     // Overload
-                    for (idx, rms) in rms.into_iter().enumerate() {
-                        let overload = rms >= 1.0;
-                        if overload {
-                            self.overload[idx] += dt * 25.0;
+                    let overloads = self.processor.take_overloads();
+                    for (idx, overload) in overloads.into_iter().enumerate() {
+                        self.latched[idx] |= overload && self.overload_latch;
+                        if overload || self.latched[idx] {
+                            self.overload[idx] += dt * LAMP_ATTACK;
                             self.overload[idx] = self.overload[idx].clamp(0.0, 1.0);
                         } else {
-                            self.overload[idx] -= dt * 5.0;
+                            self.overload[idx] -= dt * LAMP_RELEASE;
                             self.overload[idx] = self.overload[idx].clamp(0.0, 1.0);
                         }
                         let x_base = VU_WIDTH * idx as f32 + VU_WIDTH / 2.0;
 
                         // Hole
                         let mut path = Path::new();
-                        path.circle(x_base, center_y * 0.6, 7.0);
+                        path.circle(x_base, LAMP_Y, 7.0);
                         let paint = Paint::color(Color::rgbaf(0.0, 0.0, 0.0, 0.25));
                         self.canvas.fill_path(&path, &paint);
 
                         // Glow
                         let mut path = Path::new();
                         const GLOW_SIZE: f32 = 40.0;
-                        path.circle(x_base, center_y * 0.6, GLOW_SIZE);
+                        path.circle(x_base, LAMP_Y, GLOW_SIZE);
                         let paint = Paint::radial_gradient(
                             x_base,
-                            center_y * 0.6,
+                            LAMP_Y,
                             5.0,
                             GLOW_SIZE,
                             Color::rgbaf(
@@ -318,7 +560,7 @@ impl ApplicationHandler for App {
 
                         // Light
                         let mut path = Path::new();
-                        path.circle(x_base, center_y * 0.6, 5.0);
+                        path.circle(x_base, LAMP_Y, 5.0);
                         let paint = Paint::color(Color::rgbaf(
                             1.0,
                             self.overload[idx].powf(2.0) * 0.9,
@@ -328,41 +570,13 @@ impl ApplicationHandler for App {
                         self.canvas.fill_path(&path, &paint);
                     }
 
-                    let fps = (1.0 / self.perf.get_average()).max(MOTION_FILTER_CUTOFF * 2.0) as u32;
-
-                    if fps != self.last_fps {
-                        for filter in &mut self.filter {
-                            filter.update_coefficients(
-                                Coefficients::<f32>::from_params(
-                                    biquad::Type::LowPass,
-                                    fps.hz(),
-                                    MOTION_FILTER_CUTOFF.hz(),
-                                    Q_BUTTERWORTH_F32,
-                                )
-                                .unwrap(),
-                            );
-                        }
-                    }
-                    self.last_fps = fps;
-
                     // Hands
-                    for (idx, rms) in rms.into_iter().enumerate() {
-                        let rms = normalized_to_db(rms, self.negative_db_range);
-                        let rms = (rms + self.negative_db_range).max(0.0);
-                        let rms = rms / (self.negative_db_range + 6.0);
-                        let rms = rms.powf(self.bend);
-
-                        let filter = &mut self.filter[idx];
-
-                        let rms = filter.run(rms);
-                        if rms.is_nan() {
-                            info!("rms 5 is nan");
-                        }
-
+                    let needles = self.processor.get_needles_for_instant(now);
+                    for (idx, position) in needles.into_iter().enumerate() {
                         let x_base = VU_WIDTH * idx as f32 + VU_WIDTH / 2.0;
 
                         // Convert value from [0.0, 1.0] to angle range [-45°, 45°] in radians
-                        let angle = (rms * MAX_ANGLE * 2.0 - 90.0 - MAX_ANGLE)
+                        let angle = (position * MAX_ANGLE * 2.0 - 90.0 - MAX_ANGLE)
                             * (std::f32::consts::PI / 180.0); // Convert degrees to radians
 
                         // Radius (distance from center)
@@ -404,6 +618,55 @@ impl ApplicationHandler for App {
                     }
                 }
 
+                if let Some(reason) = &self.audio_lost {
+                    let mut paint = Paint::color(Color::rgb(220, 62, 73));
+                    paint.set_text_align(Align::Center);
+                    paint.set_font(&[self.font_ids[1]]);
+                    paint.set_font_size(12.0);
+                    self.canvas
+                        .fill_text(VU_WIDTH, 100.0, "NO SIGNAL / DEVICE LOST", &paint)
+                        .unwrap();
+                    paint.set_font(&[self.font_ids[0]]);
+                    paint.set_font_size(8.0);
+                    self.canvas
+                        .fill_text(VU_WIDTH, 112.0, reason, &paint)
+                        .unwrap();
+                }
+
+                if let Some((lost, at)) = self.packet_loss {
+                    // Lit while packets are going missing, dim once the stream recovers
+                    let color = if at.elapsed().as_secs_f32() < 2.0 {
+                        Color::rgb(220, 62, 73)
+                    } else {
+                        Color::rgb(80, 72, 72)
+                    };
+                    let mut paint = Paint::color(color);
+                    paint.set_text_align(Align::Center);
+                    paint.set_font(&[self.font_ids[0]]);
+                    paint.set_font_size(8.0);
+                    self.canvas
+                        .fill_text(VU_WIDTH, 12.0, format!("PACKET LOSS {lost}"), &paint)
+                        .unwrap();
+                }
+
+                if self.routing_overlay {
+                    draw_routing_overlay(
+                        &mut self.canvas,
+                        &self.font_ids,
+                        &self.routing,
+                        self.input_channels,
+                        self.stereo_mode,
+                    );
+                }
+
+                if self.clip_log_overlay {
+                    draw_clip_log_overlay(
+                        &mut self.canvas,
+                        &self.font_ids,
+                        self.processor.clip_log(),
+                    );
+                }
+
                 // self.canvas.save();
                 // self.canvas.reset();
                 // self.perf.render(&mut self.canvas, 5.0, 215.0);
@@ -424,6 +687,146 @@ impl ApplicationHandler for App {
     }
 }
 
+impl App {
+    /// Takes in audio and remote control commands that came in since the last call
+    fn receive(&mut self) {
+        while let Ok(data) = self.rx.try_recv() {
+            match data {
+                AudioEvent::Config {
+                    samplerate,
+                    channels,
+                    positioned,
+                } => {
+                    self.input_channels = channels;
+                    for panel in &mut self.panels {
+                        panel.set_samplerate(samplerate);
+                    }
+                    self.correlation.set_samplerate(samplerate);
+                    self.loudness.set_samplerate(samplerate);
+                    self.processor.set_samplerate(samplerate);
+                    self.processor.start_input(positioned);
+                    self.audio_lost = None;
+                }
+                AudioEvent::Buffer { buf } => self.consume_buf(&buf, false),
+                AudioEvent::Silence { buf } => self.consume_buf(&buf, true),
+                AudioEvent::Lost { reason } => self.audio_lost = Some(reason),
+                AudioEvent::PacketLoss { lost } => self.packet_loss = Some((lost, Instant::now())),
+            }
+        }
+
+        let commands: Vec<_> = self.osc.iter().flat_map(Osc::commands).collect();
+        for command in commands {
+            self.apply_osc(command);
+        }
+    }
+
+    /// Routes input to the panels and the meters, `silence` being filler while it's gone
+    fn consume_buf(&mut self, buf: &[f32], silence: bool) {
+        let mut buf = self.routing.apply(buf, self.input_channels);
+        for panel in &mut self.panels {
+            panel.consume_buf(&buf);
+        }
+        self.correlation.consume_buf(&buf);
+        self.loudness.consume_buf(&buf);
+        self.stereo_mode.decode(&mut buf);
+        if silence {
+            self.processor.consume_silence(buf);
+        } else {
+            self.processor.consume_buf(buf);
+        }
+    }
+
+    /// Readouts relative to full scale, `rms` being the meter level with preamp applied
+    fn readouts(&self, rms: [f32; 2]) -> [MeterReadouts; 2] {
+        let rms = rms.map(|rms| rms / self.processor.preamp);
+        let peaks = self.processor.get_peaks();
+        let max_peaks = self.processor.get_max_peaks();
+        let clips = self.processor.get_clips();
+        [0, 1].map(|idx| MeterReadouts {
+            rms: rms[idx],
+            peak: peaks[idx],
+            max_peak: max_peaks[idx],
+            clips: clips[idx],
+        })
+    }
+
+    /// Hands levels to OSC and telemetry, each sending at its own rate
+    fn publish(&mut self) {
+        if self.osc.is_none() && self.telemetry.is_none() {
+            return;
+        }
+        let readouts = self.readouts(self.processor.get_hands_for_instant(Instant::now()));
+        if let Some(osc) = &mut self.osc {
+            osc.publish(&readouts, &self.loudness);
+        }
+        if let Some(telemetry) = &mut self.telemetry {
+            telemetry.publish(&readouts, &self.loudness, self.processor.clip_log());
+        }
+    }
+
+    fn apply_osc(&mut self, command: OscCommand) {
+        match command {
+            OscCommand::Preamp(db) => {
+                let db = db.clamp(-96.0, 96.0);
+                self.processor.preamp = db_to_multiplier(db);
+                info!("preamp: {}", db);
+            }
+            OscCommand::ScaleRange(db_range) => {
+                let deflection = &mut self.processor.deflection;
+                deflection.negative_db_range = db_range.max(1.0);
+                info!("negative_db_range: {}", deflection.negative_db_range);
+            }
+            OscCommand::ScaleBend(bend) => {
+                let deflection = &mut self.processor.deflection;
+                deflection.bend = bend.max(0.1);
+                info!("bend: {}", deflection.bend);
+            }
+            OscCommand::StereoMode(stereo_mode) => {
+                self.stereo_mode = stereo_mode;
+                info!("stereo mode: {:?}", self.stereo_mode);
+            }
+            OscCommand::Integration(integration) => {
+                for channel in 0..2 {
+                    self.processor.set_integration(channel, integration);
+                }
+                info!("integration: {:?}", integration);
+            }
+            OscCommand::Ballistics(ballistics) => {
+                for channel in 0..2 {
+                    self.processor.set_ballistics(channel, ballistics);
+                }
+                info!("ballistics: {}", ballistics.name());
+            }
+            OscCommand::Reset => {
+                for idx in 0..2 {
+                    self.processor.reset_stats(idx);
+                    self.latched[idx] = false;
+                }
+                self.loudness.reset();
+                info!("reset stats, overload lamps and loudness");
+            }
+            OscCommand::Panel(name) => {
+                let by_number = name.parse::<usize>().ok().and_then(|n| n.checked_sub(1));
+                let by_name = || {
+                    self.panels
+                        .iter()
+                        .position(|panel| panel.name().eq_ignore_ascii_case(&name))
+                };
+                let found = by_number
+                    .filter(|idx| *idx < self.panels.len())
+                    .or_else(by_name);
+                match found {
+                    Some(idx) => {
+                        self.active_panel = idx;
+                        info!("panel: {}", self.panels[idx].name());
+                    }
+                    None => eprintln!("OSC: unknown panel '{name}'"),
+                }
+            }
+        }
+    }
+}
+
 pub fn multiplier_to_db(multiplier: f32) -> f32 {
     20.0 * multiplier.log10()
 }
@@ -451,6 +854,7 @@ fn run(
     surface: glutin::surface::Surface<glutin::surface::WindowSurface>,
     window: Window,
     rx: Receiver<AudioEvent>,
+    config: Config,
 ) {
     let mut font_ids = vec![];
 
@@ -491,6 +895,18 @@ fn run(
 
     canvas.scale(2.0, 2.0);
 
+    let mut processor = Processor::new();
+    processor.configure(&config);
+
+    let osc = Osc::start(&config.osc).unwrap_or_else(|err| {
+        eprintln!("cannot start OSC: {err}");
+        None
+    });
+    let telemetry = Telemetry::start(&config.telemetry).unwrap_or_else(|err| {
+        eprintln!("cannot start telemetry: {err}");
+        None
+    });
+
     let mut app = App {
         canvas,
         context,
@@ -502,35 +918,37 @@ fn run(
         perf,
         _paths: paths,
         rx,
-        processor: Processor::new(),
+        processor,
         last_hand_pos: Default::default(),
         last_last_hand_pos: Default::default(),
-        negative_db_range: 53.4,
-        bend: 2.0,
         font_ids,
         marks: generate_din_scale(),
         overload: Default::default(),
-        filter: [
-            DirectForm2Transposed::<f32>::new(
-                Coefficients::<f32>::from_params(
-                    biquad::Type::LowPass,
-                    60.hz(),
-                    MOTION_FILTER_CUTOFF.hz(),
-                    Q_BUTTERWORTH_F32,
-                )
-                .unwrap(),
-            ),
-            DirectForm2Transposed::<f32>::new(
-                Coefficients::<f32>::from_params(
-                    biquad::Type::LowPass,
-                    60.hz(),
-                    MOTION_FILTER_CUTOFF.hz(),
-                    Q_BUTTERWORTH_F32,
-                )
-                .unwrap(),
-            ),
+        overload_latch: config.overload_latch,
+        latched: [false; 2],
+        panels: vec![
+            Box::new(History::new(config.history_span, config.history_db_range)),
+            Box::new(Goniometer::new(config.goniometer_persistence)),
+            Box::new(Spectrum::new(
+                config.fft_size,
+                config.fft_window,
+                config.spectrum_averaging,
+                config.spectrum_peak_hold,
+            )),
         ],
-        last_fps: 60,
+        active_panel: 0,
+        correlation: Correlation::new(config.correlation_time),
+        loudness: Loudness::new(),
+        stereo_mode: config.stereo_mode,
+        routing: config.routing,
+        input_channels: 2,
+        routing_overlay: false,
+        clip_log_overlay: false,
+        clip_log_path: config.clip_log_path.clone(),
+        audio_lost: None,
+        packet_loss: None,
+        osc,
+        telemetry,
     };
 
     el.run_app(&mut app).unwrap();
@@ -0,0 +1,369 @@
+use std::{
+    net::{SocketAddr, UdpSocket},
+    sync::mpsc::{self, Receiver, TryIter},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail};
+use log::info;
+
+use crate::{
+    ballistics::Ballistics, integrator::Integration, loudness::Loudness, multiplier_to_db,
+    readouts::MeterReadouts, stereo_mode::StereoMode,
+};
+
+/// Published levels never go below this, as not every receiver takes -inf
+const FLOOR_DB: f32 = -120.0;
+
+#[derive(Clone, Debug)]
+pub struct OscConfig {
+    /// Address control messages are received on, no remote control if unset
+    pub listen: Option<SocketAddr>,
+    /// Where levels are published to, nothing is published if unset
+    pub send: Option<SocketAddr>,
+    /// Level messages per second
+    pub rate: f32,
+}
+
+impl Default for OscConfig {
+    fn default() -> Self {
+        Self {
+            listen: None,
+            send: None,
+            rate: 20.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum OscArg {
+    Int(i32),
+    Float(f32),
+    Str(String),
+}
+
+impl OscArg {
+    fn float(&self) -> Option<f32> {
+        match self {
+            OscArg::Int(value) => Some(*value as f32),
+            OscArg::Float(value) => Some(*value),
+            OscArg::Str(_) => None,
+        }
+    }
+}
+
+/// Remote control requests, applied by the app each time round its event loop,
+/// whether or not the window is being drawn
+#[derive(Clone, Debug, PartialEq)]
+pub enum OscCommand {
+    /// `/vu/preamp <dB>`
+    Preamp(f32),
+    /// `/vu/scale/range <dB>`, dB below 0VU covered by the scale
+    ScaleRange(f32),
+    /// `/vu/scale/bend <exponent>`
+    ScaleBend(f32),
+    /// `/vu/mode lr|ms|sum-diff`, the stereo decode feeding the meters
+    StereoMode(StereoMode),
+    /// `/vu/integration <integration>` as in the config, for both meters
+    Integration(Integration),
+    /// `/vu/ballistics <ballistics>` as in the config, for both meters
+    Ballistics(Ballistics),
+    /// `/vu/reset`, clears peak holds, clip counts, latched lamps and loudness
+    Reset,
+    /// `/vu/panel <name>` or `/vu/panel <number>` counting from 1
+    Panel(String),
+}
+
+impl OscCommand {
+    fn parse(address: &str, args: &[OscArg]) -> Result<Self, anyhow::Error> {
+        let float = || {
+            args.first()
+                .and_then(OscArg::float)
+                .filter(|value| value.is_finite())
+                .ok_or_else(|| anyhow!("'{address}' takes a number"))
+        };
+        let string = || match args.first() {
+            Some(OscArg::Str(value)) => Ok(value.clone()),
+            Some(OscArg::Int(value)) => Ok(value.to_string()),
+            _ => Err(anyhow!("'{address}' takes a name")),
+        };
+
+        Ok(match address {
+            "/vu/preamp" => OscCommand::Preamp(float()?),
+            "/vu/scale/range" => OscCommand::ScaleRange(float()?),
+            "/vu/scale/bend" => OscCommand::ScaleBend(float()?),
+            "/vu/mode" => OscCommand::StereoMode(string()?.parse()?),
+            "/vu/integration" => OscCommand::Integration(string()?.parse()?),
+            "/vu/ballistics" => OscCommand::Ballistics(string()?.parse()?),
+            "/vu/reset" => OscCommand::Reset,
+            "/vu/panel" => OscCommand::Panel(string()?),
+            _ => bail!("unknown address '{address}'"),
+        })
+    }
+}
+
+/// OSC strings are nul terminated and padded to four bytes
+fn read_string(data: &[u8], pos: &mut usize) -> Option<String> {
+    let len = data.get(*pos..)?.iter().position(|byte| *byte == 0)?;
+    let string = String::from_utf8(data[*pos..*pos + len].to_vec()).ok()?;
+    *pos += (len + 4) & !3;
+    Some(string)
+}
+
+fn read_word(data: &[u8], pos: &mut usize) -> Option<[u8; 4]> {
+    let word = data.get(*pos..*pos + 4)?.try_into().ok()?;
+    *pos += 4;
+    Some(word)
+}
+
+/// Messages in a packet, bundles flattened, their time tags ignored
+fn parse_packet(data: &[u8]) -> Option<Vec<(String, Vec<OscArg>)>> {
+    if let Some(mut elements) = data.strip_prefix(b"#bundle\0") {
+        elements = elements.get(8..)?;
+        let mut messages = vec![];
+        while !elements.is_empty() {
+            let size = u32::from_be_bytes(elements.get(..4)?.try_into().ok()?) as usize;
+            messages.extend(parse_packet(elements.get(4..4 + size)?)?);
+            elements = &elements[4 + size..];
+        }
+        return Some(messages);
+    }
+
+    let mut pos = 0;
+    let address = read_string(data, &mut pos)?;
+    // Type tags may be left out by old senders
+    let tags = read_string(data, &mut pos).unwrap_or_default();
+    let mut args = vec![];
+    for tag in tags.chars().skip(1) {
+        args.push(match tag {
+            'i' => OscArg::Int(i32::from_be_bytes(read_word(data, &mut pos)?)),
+            'f' => OscArg::Float(f32::from_be_bytes(read_word(data, &mut pos)?)),
+            's' => OscArg::Str(read_string(data, &mut pos)?),
+            // Argument types without data, like booleans
+            'T' | 'F' | 'N' | 'I' => continue,
+            _ => return None,
+        });
+    }
+    Some(vec![(address, args)])
+}
+
+fn write_string(packet: &mut Vec<u8>, string: &str) {
+    packet.extend(string.as_bytes());
+    packet.resize((packet.len() + 4) & !3, 0);
+}
+
+fn encode(address: &str, args: &[OscArg]) -> Vec<u8> {
+    let mut packet = vec![];
+    write_string(&mut packet, address);
+    let tags: String = args
+        .iter()
+        .map(|arg| match arg {
+            OscArg::Int(_) => 'i',
+            OscArg::Float(_) => 'f',
+            OscArg::Str(_) => 's',
+        })
+        .collect();
+    write_string(&mut packet, &format!(",{tags}"));
+    for arg in args {
+        match arg {
+            OscArg::Int(value) => packet.extend(value.to_be_bytes()),
+            OscArg::Float(value) => packet.extend(value.to_be_bytes()),
+            OscArg::Str(value) => write_string(&mut packet, value),
+        }
+    }
+    packet
+}
+
+/// OSC remote control and level publishing
+pub struct Osc {
+    commands: Receiver<OscCommand>,
+    socket: UdpSocket,
+    send: Option<SocketAddr>,
+    interval: Duration,
+    last_sent: Instant,
+}
+
+impl Osc {
+    /// Starts listening for control messages, None if OSC isn't configured
+    pub fn start(config: &OscConfig) -> Result<Option<Self>, anyhow::Error> {
+        if config.listen.is_none() && config.send.is_none() {
+            return Ok(None);
+        }
+
+        let socket = match config.listen {
+            Some(listen) => UdpSocket::bind(listen)?,
+            None => UdpSocket::bind(("0.0.0.0", 0))?,
+        };
+        let (tx, commands) = mpsc::channel();
+        if let Some(listen) = config.listen {
+            info!("OSC control on {listen}");
+            let socket = socket.try_clone()?;
+            std::thread::spawn(move || receive(socket, tx));
+        }
+        if let Some(send) = config.send {
+            info!("OSC levels to {send} at {}Hz", config.rate);
+        }
+
+        Ok(Some(Self {
+            commands,
+            socket,
+            send: config.send,
+            interval: Duration::from_secs_f32(1.0 / config.rate),
+            last_sent: Instant::now(),
+        }))
+    }
+
+    pub fn commands(&self) -> TryIter<'_, OscCommand> {
+        self.commands.try_iter()
+    }
+
+    /// Sends `/vu/meter/<n> rms peak max clips momentary short_term`, levels in dB and
+    /// the programme loudness in LUFS on every meter, at the configured rate. Called
+    /// every time round the event loop, so a hidden window keeps publishing.
+    pub fn publish(&mut self, readouts: &[MeterReadouts; 2], loudness: &Loudness) {
+        let Some(send) = self.send else {
+            return;
+        };
+        if self.last_sent.elapsed() < self.interval {
+            return;
+        }
+        self.last_sent = Instant::now();
+
+        let db = |level: f32| OscArg::Float(multiplier_to_db(level).max(FLOOR_DB));
+        // Not measured yet reads as silence
+        let lufs = |lufs: Option<f32>| OscArg::Float(lufs.unwrap_or(FLOOR_DB).max(FLOOR_DB));
+        for (idx, readouts) in readouts.iter().enumerate() {
+            let args = [
+                db(readouts.rms),
+                db(readouts.peak),
+                db(readouts.max_peak),
+                OscArg::Int(readouts.clips.min(i32::MAX as u64) as i32),
+                lufs(loudness.momentary()),
+                lufs(loudness.short_term()),
+            ];
+            let packet = encode(&format!("/vu/meter/{}", idx + 1), &args);
+            if let Err(err) = self.socket.send_to(&packet, send) {
+                eprintln!("cannot send OSC levels: {err}");
+            }
+        }
+    }
+}
+
+fn receive(socket: UdpSocket, tx: mpsc::Sender<OscCommand>) {
+    let mut packet = vec![0; 65536];
+    loop {
+        let len = match socket.recv(&mut packet) {
+            Ok(len) => len,
+            Err(err) => {
+                eprintln!("OSC receive failed: {err}");
+                return;
+            }
+        };
+        let Some(messages) = parse_packet(&packet[..len]) else {
+            eprintln!("malformed OSC packet");
+            continue;
+        };
+        for (address, args) in messages {
+            match OscCommand::parse(&address, &args) {
+                Ok(command) => {
+                    if tx.send(command).is_err() {
+                        return;
+                    }
+                }
+                Err(err) => eprintln!("OSC: {err}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use super::*;
+    use crate::needle::Movement;
+
+    #[test]
+    fn round_trips_messages_and_bundles() {
+        let preamp = encode("/vu/preamp", &[OscArg::Float(12.0)]);
+        let mode = encode("/vu/mode", &[OscArg::Str("ms".into())]);
+        assert!(preamp.len().is_multiple_of(4) && mode.len().is_multiple_of(4));
+
+        let mut bundle = b"#bundle\0".to_vec();
+        bundle.extend([0, 0, 0, 0, 0, 0, 0, 1]);
+        for message in [preamp, mode] {
+            bundle.extend((message.len() as u32).to_be_bytes());
+            bundle.extend(message);
+        }
+
+        let commands: Vec<_> = parse_packet(&bundle)
+            .unwrap()
+            .iter()
+            .map(|(address, args)| OscCommand::parse(address, args).unwrap())
+            .collect();
+        assert_eq!(
+            commands,
+            [
+                OscCommand::Preamp(12.0),
+                OscCommand::StereoMode(StereoMode::MidSide)
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_meter_mode_commands() {
+        let parse = |address, arg: &str| OscCommand::parse(address, &[OscArg::Str(arg.into())]);
+        assert_eq!(
+            parse("/vu/integration", "rc:300ms").unwrap(),
+            OscCommand::Integration(Integration::Exponential(0.3))
+        );
+        assert_eq!(
+            parse("/vu/ballistics", "sifam-ppm").unwrap(),
+            OscCommand::Ballistics(Ballistics::Mechanical(Movement::SifamPpm))
+        );
+        assert!(parse("/vu/ballistics", "wobbly").is_err());
+    }
+
+    #[test]
+    fn publishes_levels_and_loudness_per_meter() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let config = OscConfig {
+            listen: None,
+            send: Some(receiver.local_addr().unwrap()),
+            rate: 1000.0,
+        };
+        let mut osc = Osc::start(&config).unwrap().unwrap();
+
+        let readouts = [0.5, 0.0].map(|level| MeterReadouts {
+            rms: level,
+            peak: level,
+            max_peak: level,
+            clips: 2,
+        });
+        // Half a second of a full scale 997Hz sine on the left, -3.01LUFS
+        let mut loudness = Loudness::new();
+        let sine: Vec<f32> = (0..22050)
+            .flat_map(|n| [(TAU * 997.0 * n as f32 / 44100.0).sin(), 0.0])
+            .collect();
+        loudness.consume_buf(&sine);
+        std::thread::sleep(Duration::from_millis(2));
+        osc.publish(&readouts, &loudness);
+
+        let mut packet = vec![0; 1024];
+        for meter in 1..=2 {
+            let len = receiver.recv(&mut packet).unwrap();
+            let messages = parse_packet(&packet[..len]).unwrap();
+            let (address, args) = &messages[0];
+            assert_eq!(address, &format!("/vu/meter/{meter}"));
+            let level = if meter == 1 { -6.0206 } else { FLOOR_DB };
+            assert!((args[0].float().unwrap() - level).abs() < 0.001);
+            assert_eq!(args[3], OscArg::Int(2));
+            // The same programme loudness on both, short-term not measured yet
+            assert!((args[4].float().unwrap() + 3.01).abs() < 0.02);
+            assert_eq!(args[5], OscArg::Float(FLOOR_DB));
+        }
+    }
+}
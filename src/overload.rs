@@ -0,0 +1,202 @@
+use std::{f32::consts::PI, str::FromStr};
+
+use anyhow::{anyhow, bail};
+
+/// Overload lamp centre, level with the meter labels
+pub const LAMP_Y: f32 = 207.0 * 0.6;
+const LAMP_HIT_RADIUS: f32 = 10.0;
+/// Lamp brightness change per second while lighting up and fading out
+pub const LAMP_ATTACK: f32 = 25.0;
+pub const LAMP_RELEASE: f32 = 5.0;
+
+/// True peak interpolation, 4x oversampling with 12 taps per phase
+const OVERSAMPLING: usize = 4;
+const TAPS_PER_PHASE: usize = 12;
+
+/// What lights the overload lamp
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverloadTrigger {
+    SamplePeak,
+    /// Inter-sample peak estimated by 4x oversampling, like BS.1770 true peak
+    TruePeak,
+    Rms,
+    /// That many consecutive samples at or above the threshold
+    Consecutive(usize),
+}
+
+/// Parses `peak`, `true-peak`, `rms` or `consecutive:<samples>`
+impl FromStr for OverloadTrigger {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("consecutive", samples)) => {
+                let samples: usize = samples
+                    .parse()
+                    .map_err(|_| anyhow!("invalid sample count '{samples}'"))?;
+                if samples == 0 {
+                    bail!("consecutive sample count must be positive");
+                }
+                Ok(OverloadTrigger::Consecutive(samples))
+            }
+            None if s == "peak" => Ok(OverloadTrigger::SamplePeak),
+            None if s == "true-peak" => Ok(OverloadTrigger::TruePeak),
+            None if s == "rms" => Ok(OverloadTrigger::Rms),
+            _ => Err(anyhow!("unknown overload trigger '{s}'")),
+        }
+    }
+}
+
+/// Overload detection for a single channel, fed every sample
+pub struct OverloadDetector {
+    trigger: OverloadTrigger,
+    /// Linear threshold
+    threshold: f32,
+    consecutive: usize,
+    history: [f32; TAPS_PER_PHASE],
+    phases: [[f32; TAPS_PER_PHASE]; OVERSAMPLING],
+}
+
+impl OverloadDetector {
+    pub fn new(trigger: OverloadTrigger, threshold: f32) -> Self {
+        Self {
+            trigger,
+            threshold,
+            consecutive: 0,
+            history: [0.0; TAPS_PER_PHASE],
+            phases: interpolation_phases(),
+        }
+    }
+
+    /// Takes a sample and the mean square after it, both relative to full scale
+    /// before the preamp.
+    /// Returns whether the channel is overloaded at this sample.
+    pub fn push(&mut self, sample: f32, mean_square: f64) -> bool {
+        match self.trigger {
+            OverloadTrigger::SamplePeak => sample.abs() >= self.threshold,
+            OverloadTrigger::TruePeak => {
+                self.history.rotate_right(1);
+                self.history[0] = sample;
+                self.phases.iter().any(|taps| {
+                    let interpolated: f32 =
+                        taps.iter().zip(&self.history).map(|(h, x)| h * x).sum();
+                    interpolated.abs() >= self.threshold
+                })
+            }
+            OverloadTrigger::Rms => mean_square.sqrt() as f32 >= self.threshold,
+            OverloadTrigger::Consecutive(samples) => {
+                if sample.abs() >= self.threshold {
+                    self.consecutive += 1;
+                } else {
+                    self.consecutive = 0;
+                }
+                self.consecutive >= samples
+            }
+        }
+    }
+}
+
+/// Hann-windowed sinc interpolator split into polyphase branches, each normalized
+/// to unity gain at DC
+fn interpolation_phases() -> [[f32; TAPS_PER_PHASE]; OVERSAMPLING] {
+    // Centred on a tap so phase 0 passes the original samples through
+    let centre = (TAPS_PER_PHASE / 2) as f32;
+    std::array::from_fn(|phase| {
+        let mut taps: [f32; TAPS_PER_PHASE] = std::array::from_fn(|k| {
+            let t = k as f32 - centre + phase as f32 / OVERSAMPLING as f32;
+            let sinc = if t == 0.0 {
+                1.0
+            } else {
+                (PI * t).sin() / (PI * t)
+            };
+            let window = 0.5 + 0.5 * (PI * t / centre).cos();
+            sinc * window
+        });
+        let sum: f32 = taps.iter().sum();
+        taps.iter_mut().for_each(|tap| *tap /= sum);
+        taps
+    })
+}
+
+/// Whether a point in canvas coordinates is on the overload lamp of the meter at `x_base`
+pub fn lamp_hit(x_base: f32, point: (f32, f32)) -> bool {
+    (point.0 - x_base).hypot(point.1 - LAMP_Y) <= LAMP_HIT_RADIUS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db_to_multiplier, multiplier_to_db};
+
+    #[test]
+    fn parses_triggers() {
+        assert_eq!(
+            "peak".parse::<OverloadTrigger>().unwrap(),
+            OverloadTrigger::SamplePeak
+        );
+        assert_eq!(
+            "true-peak".parse::<OverloadTrigger>().unwrap(),
+            OverloadTrigger::TruePeak
+        );
+        assert_eq!(
+            "rms".parse::<OverloadTrigger>().unwrap(),
+            OverloadTrigger::Rms
+        );
+        assert_eq!(
+            "consecutive:3".parse::<OverloadTrigger>().unwrap(),
+            OverloadTrigger::Consecutive(3)
+        );
+        for trigger in [
+            "consecutive:0",
+            "consecutive:x",
+            "consecutive",
+            "peak:1",
+            "loud",
+            "",
+        ] {
+            assert!(
+                trigger.parse::<OverloadTrigger>().is_err(),
+                "accepted '{trigger}'"
+            );
+        }
+    }
+
+    /// A full scale sine at fs/4, 45 degrees out, peaks halfway between samples
+    /// which all sit 3dB down
+    fn inter_sample_peaks() -> Vec<f32> {
+        let s = std::f32::consts::FRAC_1_SQRT_2;
+        [s, s, -s, -s].repeat(250)
+    }
+
+    fn overloads(trigger: OverloadTrigger, threshold_db: f32, signal: &[f32]) -> bool {
+        let mut detector = OverloadDetector::new(trigger, db_to_multiplier(threshold_db));
+        let overloaded: Vec<bool> = signal
+            .iter()
+            .map(|sample| detector.push(*sample, 0.0))
+            .collect();
+        // Past the interpolator ringing at the abrupt start
+        overloaded[TAPS_PER_PHASE..]
+            .iter()
+            .any(|overloaded| *overloaded)
+    }
+
+    #[test]
+    fn true_peak_catches_inter_sample_overs() {
+        let signal = inter_sample_peaks();
+        assert!(signal
+            .iter()
+            .all(|sample| multiplier_to_db(sample.abs()) < -3.0));
+
+        // The interpolator reads a fs/4 peak within 0.05dB
+        assert!(overloads(OverloadTrigger::TruePeak, -0.05, &signal));
+        assert!(!overloads(OverloadTrigger::SamplePeak, -0.05, &signal));
+        assert!(!overloads(OverloadTrigger::TruePeak, 0.05, &signal));
+    }
+
+    #[test]
+    fn consecutive_trigger_needs_a_run_of_samples() {
+        let run = |len| [vec![0.0; 20], vec![1.0; len], vec![0.0; 10]].concat();
+        assert!(!overloads(OverloadTrigger::Consecutive(3), 0.0, &run(2)));
+        assert!(overloads(OverloadTrigger::Consecutive(3), 0.0, &run(3)));
+    }
+}
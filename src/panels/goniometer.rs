@@ -0,0 +1,235 @@
+use std::collections::VecDeque;
+
+use femtovg::{renderer::OpenGl, Align, Canvas, Color, FontId, Paint, Path};
+use winit::keyboard::KeyCode;
+
+use super::{Levels, Panel};
+
+// Points kept per frame, raw buffers are decimated down to this and only the
+// newest are kept when buffers pile up between frames
+const MAX_FRAME_POINTS: usize = 1024;
+const MIN_GAIN: f32 = 1.0;
+const MAX_GAIN: f32 = 1000.0;
+
+struct Frame {
+    age: f32,
+    points: Vec<(f32, f32)>,
+}
+
+/// M/S vectorscope of raw sample pairs: mono is vertical, out of phase is horizontal
+pub struct Goniometer {
+    persistence: f32,
+    frames: VecDeque<Frame>,
+    current: VecDeque<(f32, f32)>,
+    lines: bool,
+    auto_gain: bool,
+    gain: f32,
+}
+
+impl Goniometer {
+    pub fn new(persistence: f32) -> Self {
+        Self {
+            persistence,
+            frames: VecDeque::new(),
+            current: VecDeque::with_capacity(MAX_FRAME_POINTS),
+            lines: false,
+            auto_gain: true,
+            gain: MIN_GAIN,
+        }
+    }
+
+    fn update_gain(&mut self, dt: f32) {
+        if !self.auto_gain {
+            self.gain = MIN_GAIN;
+            return;
+        }
+
+        let max = self
+            .frames
+            .iter()
+            .flat_map(|frame| frame.points.iter())
+            .map(|(x, y)| x.abs().max(y.abs()))
+            .fold(0.0, f32::max);
+        let target = if max > 0.0 {
+            (0.9 / max).clamp(MIN_GAIN, MAX_GAIN)
+        } else {
+            self.gain
+        };
+
+        // Back off quickly on louder material, creep up slowly on quieter
+        let rate = if target < self.gain { 20.0 } else { 1.0 };
+        self.gain += (target - self.gain) * (dt * rate).min(1.0);
+    }
+}
+
+impl Panel for Goniometer {
+    fn name(&self) -> &'static str {
+        "GONIOMETER"
+    }
+
+    fn consume_buf(&mut self, buf: &[f32]) {
+        let stride = (buf.len() / 2 / MAX_FRAME_POINTS).max(1);
+        for pair in buf.chunks_exact(2).step_by(stride) {
+            let (l, r) = (pair[0], pair[1]);
+            let side = (r - l) * std::f32::consts::FRAC_1_SQRT_2;
+            let mid = (l + r) * std::f32::consts::FRAC_1_SQRT_2;
+            if self.current.len() == MAX_FRAME_POINTS {
+                self.current.pop_front();
+            }
+            self.current.push_back((side, mid));
+        }
+    }
+
+    fn update(&mut self, _levels: &Levels, dt: f32) {
+        for frame in &mut self.frames {
+            frame.age += dt;
+        }
+        while self
+            .frames
+            .front()
+            .is_some_and(|frame| frame.age > self.persistence)
+        {
+            self.frames.pop_front();
+        }
+
+        let points: Vec<_> = self.current.drain(..).collect();
+        if !points.is_empty() {
+            self.frames.push_back(Frame { age: 0.0, points });
+        }
+
+        self.update_gain(dt);
+    }
+
+    fn key(&mut self, key_code: KeyCode) {
+        match key_code {
+            KeyCode::KeyD => self.lines = !self.lines,
+            KeyCode::KeyG => self.auto_gain = !self.auto_gain,
+            _ => {}
+        }
+    }
+
+    fn draw(
+        &self,
+        canvas: &mut Canvas<OpenGl>,
+        font_ids: &[FontId],
+        x: f32,
+        y: f32,
+        w: f32,
+        h: f32,
+    ) {
+        let center_x = x + w / 2.0;
+        let center_y = y + h / 2.0;
+        let radius = h / 2.0 - 8.0;
+
+        // Axes: L and R diagonals, M vertical, S horizontal
+        let mut grid_paint = Paint::color(Color::rgb(52, 46, 46));
+        grid_paint.set_line_width(1.0);
+        let diagonal = radius * std::f32::consts::FRAC_1_SQRT_2;
+        let mut path = Path::new();
+        path.move_to(center_x - diagonal, center_y - diagonal);
+        path.line_to(center_x + diagonal, center_y + diagonal);
+        path.move_to(center_x + diagonal, center_y - diagonal);
+        path.line_to(center_x - diagonal, center_y + diagonal);
+        path.move_to(center_x, center_y - radius);
+        path.line_to(center_x, center_y + radius);
+        path.move_to(center_x - radius, center_y);
+        path.line_to(center_x + radius, center_y);
+        path.circle(center_x, center_y, radius);
+        canvas.stroke_path(&path, &grid_paint);
+
+        let mut label_paint = Paint::color(Color::rgb(80, 72, 72));
+        label_paint.set_font(&[font_ids[0]]);
+        label_paint.set_font_size(8.0);
+        label_paint.set_text_align(Align::Center);
+        for (label, lx, ly) in [
+            ("L", -diagonal - 4.0, -diagonal - 2.0),
+            ("R", diagonal + 4.0, -diagonal - 2.0),
+            ("M", 0.0, -radius - 2.0),
+            ("S", radius + 6.0, 3.0),
+        ] {
+            canvas
+                .fill_text(center_x + lx, center_y + ly, label, &label_paint)
+                .unwrap();
+        }
+
+        label_paint.set_text_align(Align::Left);
+        let gain_label = if self.auto_gain {
+            format!("AUTO x{:.1}", self.gain)
+        } else {
+            "x1.0".into()
+        };
+        canvas
+            .fill_text(x + 4.0, y + 10.0, gain_label, &label_paint)
+            .unwrap();
+
+        // Traces, older frames fade out
+        canvas.save();
+        canvas.intersect_scissor(
+            center_x - radius,
+            center_y - radius,
+            radius * 2.0,
+            radius * 2.0,
+        );
+        for frame in &self.frames {
+            let alpha = (1.0 - frame.age / self.persistence).clamp(0.0, 1.0);
+            let color = Color::rgbaf(1.0, 0.78, 0.63, alpha * 0.8);
+            let to_screen = |(side, mid): (f32, f32)| {
+                (
+                    center_x + side * self.gain * radius,
+                    center_y - mid * self.gain * radius,
+                )
+            };
+
+            let mut path = Path::new();
+            if self.lines {
+                for (idx, point) in frame.points.iter().enumerate() {
+                    let (px, py) = to_screen(*point);
+                    if idx == 0 {
+                        path.move_to(px, py);
+                    } else {
+                        path.line_to(px, py);
+                    }
+                }
+                let mut paint = Paint::color(color);
+                paint.set_line_width(0.5);
+                canvas.stroke_path(&path, &paint);
+            } else {
+                for point in &frame.points {
+                    let (px, py) = to_screen(*point);
+                    path.rect(px - 0.5, py - 0.5, 1.0, 1.0);
+                }
+                canvas.fill_path(&path, &Paint::color(color));
+            }
+        }
+        canvas.restore();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn points_are_capped_between_frames() {
+        let mut goniometer = Goniometer::new(0.3);
+        // Small buffers aren't decimated, so without frames they would pile up
+        for n in 0..MAX_FRAME_POINTS * 4 {
+            goniometer.consume_buf(&[n as f32, n as f32]);
+        }
+        assert_eq!(goniometer.current.len(), MAX_FRAME_POINTS);
+
+        let levels = Levels {
+            rms: [0.0; 2],
+            peak: [0.0; 2],
+        };
+        goniometer.update(&levels, 0.01);
+        let points = &goniometer.frames[0].points;
+        assert_eq!(points.len(), MAX_FRAME_POINTS);
+        // The newest are kept
+        let newest = (MAX_FRAME_POINTS * 4 - 1) as f32;
+        assert_eq!(
+            points.last().unwrap().1,
+            (newest + newest) * std::f32::consts::FRAC_1_SQRT_2
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use femtovg::{renderer::OpenGl, Align, Canvas, Color, FontId, Paint, Path};
+
+use crate::multiplier_to_db;
+
+use super::{Levels, Panel};
+
+const HISTORY_COUNT: usize = 640;
+
+/// Scrolling dB-over-time chart of RMS and peak envelopes per channel
+pub struct History {
+    span: f32,
+    db_range: f32,
+    rms: Vec<[f32; 2]>,
+    peak: Vec<[f32; 2]>,
+    head: usize,
+    elapsed: f32,
+    pending_rms: [f32; 2],
+    pending_peak: [f32; 2],
+}
+
+impl History {
+    pub fn new(span: f32, db_range: f32) -> Self {
+        Self {
+            span,
+            db_range,
+            rms: vec![[0.0; 2]; HISTORY_COUNT],
+            peak: vec![[0.0; 2]; HISTORY_COUNT],
+            head: 0,
+            elapsed: 0.0,
+            pending_rms: [0.0; 2],
+            pending_peak: [0.0; 2],
+        }
+    }
+
+    fn push(&mut self) {
+        self.head = (self.head + 1) % HISTORY_COUNT;
+        self.rms[self.head] = self.pending_rms;
+        self.peak[self.head] = self.pending_peak;
+    }
+
+    /// Maps a linear level to [0.0, 1.0] over the configured dB range
+    fn level_to_y(&self, value: f32) -> f32 {
+        if value <= 0.0 {
+            return 0.0;
+        }
+        ((multiplier_to_db(value) + self.db_range) / self.db_range).clamp(0.0, 1.0)
+    }
+}
+
+impl Panel for History {
+    fn name(&self) -> &'static str {
+        "HISTORY"
+    }
+
+    fn update(&mut self, levels: &Levels, dt: f32) {
+        // Every column holds the loudest levels seen during its interval
+        for ch in 0..2 {
+            self.pending_rms[ch] = self.pending_rms[ch].max(levels.rms[ch]);
+            self.pending_peak[ch] = self.pending_peak[ch].max(levels.peak[ch]);
+        }
+
+        let interval = self.span / HISTORY_COUNT as f32;
+        self.elapsed += dt;
+        if self.elapsed < interval {
+            return;
+        }
+        while self.elapsed >= interval {
+            self.push();
+            self.elapsed -= interval;
+        }
+        self.pending_rms = [0.0; 2];
+        self.pending_peak = [0.0; 2];
+    }
+
+    fn draw(
+        &self,
+        canvas: &mut Canvas<OpenGl>,
+        font_ids: &[FontId],
+        x: f32,
+        y: f32,
+        w: f32,
+        h: f32,
+    ) {
+        let lane_h = h / 2.0;
+
+        let mut label_paint = Paint::color(Color::rgb(80, 72, 72));
+        label_paint.set_font(&[font_ids[0]]);
+        label_paint.set_font_size(8.0);
+        label_paint.set_text_align(Align::Right);
+
+        for ch in 0..2 {
+            let lane_y = y + lane_h * ch as f32;
+
+            // Grid every 10dB
+            let mut db = 0.0;
+            while db <= self.db_range {
+                let gy = lane_y + lane_h * (db / self.db_range);
+                let mut path = Path::new();
+                path.move_to(x + 24.0, gy);
+                path.line_to(x + w, gy);
+                let mut paint = Paint::color(Color::rgb(52, 46, 46));
+                paint.set_line_width(1.0);
+                canvas.stroke_path(&path, &paint);
+                if db < self.db_range {
+                    canvas
+                        .fill_text(x + 20.0, gy + 8.0, format!("-{db}"), &label_paint)
+                        .unwrap();
+                }
+                db += 10.0;
+            }
+
+            let column_w = (w - 24.0) / (HISTORY_COUNT - 1) as f32;
+            let column_x = |i: usize| x + 24.0 + i as f32 * column_w;
+            let value_y = |value: f32| lane_y + lane_h * (1.0 - self.level_to_y(value));
+
+            // Peak envelope
+            let mut path = Path::new();
+            path.move_to(column_x(0), lane_y + lane_h);
+            for i in 0..HISTORY_COUNT {
+                let value = self.peak[(self.head + 1 + i) % HISTORY_COUNT][ch];
+                path.line_to(column_x(i), value_y(value));
+            }
+            path.line_to(column_x(HISTORY_COUNT - 1), lane_y + lane_h);
+            canvas.fill_path(&path, &Paint::color(Color::rgba(255, 48, 0, 60)));
+
+            // RMS
+            let mut path = Path::new();
+            for i in 0..HISTORY_COUNT {
+                let value = self.rms[(self.head + 1 + i) % HISTORY_COUNT][ch];
+                if i == 0 {
+                    path.move_to(column_x(i), value_y(value));
+                } else {
+                    path.line_to(column_x(i), value_y(value));
+                }
+            }
+            let mut paint = Paint::color(Color::rgb(255, 200, 160));
+            paint.set_line_width(1.0);
+            canvas.stroke_path(&path, &paint);
+        }
+
+        label_paint.set_text_align(Align::Left);
+        canvas
+            .fill_text(
+                x + 28.0,
+                y + 10.0,
+                format!("{:.0}s", self.span),
+                &label_paint,
+            )
+            .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Column interval of 1/128s, exact in binary
+    const INTERVAL: f32 = 1.0 / 128.0;
+
+    fn new_history() -> History {
+        History::new(INTERVAL * HISTORY_COUNT as f32, 60.0)
+    }
+
+    fn levels(rms: f32) -> Levels {
+        Levels {
+            rms: [rms; 2],
+            peak: [rms * 2.0; 2],
+        }
+    }
+
+    #[test]
+    fn columns_advance_with_elapsed_time_not_frames() {
+        // Frames slower than the column interval fill several columns each
+        let mut history = new_history();
+        for _ in 0..10 {
+            history.update(&levels(0.5), INTERVAL * 2.0);
+        }
+        assert_eq!(history.head, 20);
+
+        // Frames faster than it share a column
+        let mut history = new_history();
+        for _ in 0..10 {
+            history.update(&levels(0.5), INTERVAL / 4.0);
+        }
+        assert_eq!(history.head, 2);
+    }
+
+    #[test]
+    fn column_holds_the_loudest_frame_of_its_interval() {
+        let mut history = new_history();
+        for rms in [0.1, 0.5, 0.2, 0.3] {
+            history.update(&levels(rms), INTERVAL / 4.0);
+        }
+        assert_eq!(history.head, 1);
+        assert_eq!(history.rms[1], [0.5; 2]);
+        assert_eq!(history.peak[1], [1.0; 2]);
+
+        // The next column starts over
+        for _ in 0..4 {
+            history.update(&levels(0.1), INTERVAL / 4.0);
+        }
+        assert_eq!(history.rms[2], [0.1; 2]);
+    }
+
+    #[test]
+    fn slow_frame_repeats_its_levels_across_columns() {
+        let mut history = new_history();
+        history.update(&levels(0.25), INTERVAL * 3.0);
+        assert_eq!(history.head, 3);
+        assert_eq!(&history.rms[1..4], &[[0.25; 2]; 3]);
+        assert_eq!(history.rms[4], [0.0; 2]);
+    }
+}
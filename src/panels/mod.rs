@@ -0,0 +1,42 @@
+use femtovg::{renderer::OpenGl, Canvas, FontId};
+use winit::keyboard::KeyCode;
+
+mod goniometer;
+mod history;
+mod spectrum;
+
+pub use goniometer::Goniometer;
+pub use history::History;
+pub use spectrum::Spectrum;
+
+/// Per-frame meter levels as linear multipliers, preamp applied
+pub struct Levels {
+    pub rms: [f32; 2],
+    pub peak: [f32; 2],
+}
+
+/// A view drawn in the strip below the meters
+pub trait Panel {
+    fn name(&self) -> &'static str;
+
+    fn set_samplerate(&mut self, _samplerate: usize) {}
+
+    /// Called with every raw interleaved stereo buffer, before the processor sees it
+    fn consume_buf(&mut self, _buf: &[f32]) {}
+
+    /// Called once per frame for every panel, shown or not
+    fn update(&mut self, levels: &Levels, dt: f32);
+
+    /// Key presses not handled by the app go to the shown panel
+    fn key(&mut self, _key_code: KeyCode) {}
+
+    fn draw(
+        &self,
+        canvas: &mut Canvas<OpenGl>,
+        font_ids: &[FontId],
+        x: f32,
+        y: f32,
+        w: f32,
+        h: f32,
+    );
+}
@@ -0,0 +1,415 @@
+use std::collections::VecDeque;
+
+use femtovg::{renderer::OpenGl, Align, Canvas, Color, FontId, Paint, Path};
+use winit::keyboard::KeyCode;
+
+use crate::fft::{fft, WindowFunction, FFT_SIZES};
+
+use super::{Levels, Panel};
+
+const DB_RANGE: f32 = 90.0;
+const MIN_FREQ: f32 = 20.0;
+const MAX_FREQ: f32 = 20000.0;
+const PEAK_FALLBACK_DB_PER_SEC: f32 = 20.0;
+const AVERAGING_STEPS: [f32; 4] = [0.0, 0.1, 0.5, 2.0];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Bands,
+    Curve,
+}
+
+/// FFT spectrum of the mono sum, as 1/3-octave bands or a log-frequency curve
+pub struct Spectrum {
+    samplerate: usize,
+    fft_size: usize,
+    window_function: WindowFunction,
+    window: Vec<f32>,
+    averaging: f32,
+    peak_hold: f32,
+    mode: Mode,
+    show_peaks: bool,
+    samples: VecDeque<f32>,
+    fresh: bool,
+    re: Vec<f32>,
+    im: Vec<f32>,
+    /// Averaged power per bin, relative to a full scale sine
+    power: Vec<f32>,
+    peak_db: Vec<f32>,
+    peak_age: Vec<f32>,
+}
+
+impl Spectrum {
+    pub fn new(
+        fft_size: usize,
+        window_function: WindowFunction,
+        averaging: f32,
+        peak_hold: f32,
+    ) -> Self {
+        let mut spectrum = Self {
+            samplerate: 44100,
+            fft_size,
+            window_function,
+            window: vec![],
+            averaging,
+            peak_hold,
+            mode: Mode::Bands,
+            show_peaks: true,
+            samples: VecDeque::new(),
+            fresh: false,
+            re: vec![],
+            im: vec![],
+            power: vec![],
+            peak_db: vec![],
+            peak_age: vec![],
+        };
+        spectrum.resize();
+        spectrum
+    }
+
+    fn resize(&mut self) {
+        let bins = self.fft_size / 2;
+        self.window = self.window_function.coefficients(self.fft_size);
+        self.re = vec![0.0; self.fft_size];
+        self.im = vec![0.0; self.fft_size];
+        self.power = vec![0.0; bins];
+        self.peak_db = vec![-DB_RANGE; bins];
+        self.peak_age = vec![0.0; bins];
+    }
+
+    fn analyze(&mut self, dt: f32) {
+        for (idx, sample) in self.samples.iter().enumerate() {
+            self.re[idx] = sample * self.window[idx];
+            self.im[idx] = 0.0;
+        }
+        fft(&mut self.re, &mut self.im);
+
+        // Normalize so a full scale sine reads 0dB whatever the window
+        let window_sum: f32 = self.window.iter().sum();
+        let scale = 2.0 / window_sum;
+        let coeff = if self.averaging > 0.0 {
+            1.0 - (-dt / self.averaging).exp()
+        } else {
+            1.0
+        };
+
+        for bin in 0..self.power.len() {
+            let magnitude = (self.re[bin].powi(2) + self.im[bin].powi(2)).sqrt() * scale;
+            let power = magnitude * magnitude;
+            self.power[bin] += (power - self.power[bin]) * coeff;
+
+            let db = power_to_db(self.power[bin]);
+            if db >= self.peak_db[bin] {
+                self.peak_db[bin] = db;
+                self.peak_age[bin] = 0.0;
+            } else {
+                self.peak_age[bin] += dt;
+                if self.peak_age[bin] > self.peak_hold {
+                    self.peak_db[bin] = (self.peak_db[bin] - PEAK_FALLBACK_DB_PER_SEC * dt).max(db);
+                }
+            }
+        }
+    }
+
+    fn bin_freq(&self, bin: usize) -> f32 {
+        bin as f32 * self.samplerate as f32 / self.fft_size as f32
+    }
+
+    /// Equivalent noise bandwidth of the window in bins, how much wider than one
+    /// bin it spreads power
+    fn enbw(&self) -> f32 {
+        let sum: f32 = self.window.iter().sum();
+        let sum_squares: f32 = self.window.iter().map(|w| w * w).sum();
+        self.window.len() as f32 * sum_squares / (sum * sum)
+    }
+
+    /// Sums bin powers in each 1/3-octave band from 25Hz to 20kHz. Bins are scaled
+    /// for a sine peak, so the sum is divided by the ENBW to count its power once.
+    fn bands(&self, values: &[f32]) -> Vec<(f32, f32)> {
+        let enbw = self.enbw();
+        (-16..=13)
+            .map(|n| {
+                let center = 1000.0 * 2.0f32.powf(n as f32 / 3.0);
+                let lo = center * 2.0f32.powf(-1.0 / 6.0);
+                let hi = center * 2.0f32.powf(1.0 / 6.0);
+                let bin_lo = (lo * self.fft_size as f32 / self.samplerate as f32).ceil() as usize;
+                let bin_hi = (hi * self.fft_size as f32 / self.samplerate as f32).ceil() as usize;
+                let bin_hi = bin_hi.min(values.len());
+
+                let power = if bin_lo < bin_hi {
+                    values[bin_lo..bin_hi].iter().sum::<f32>() / enbw
+                } else {
+                    // Band narrower than the bin spacing, use the nearest bin
+                    let nearest =
+                        (center * self.fft_size as f32 / self.samplerate as f32).round() as usize;
+                    values.get(nearest).copied().unwrap_or(0.0)
+                };
+                (center, power)
+            })
+            .collect()
+    }
+}
+
+fn power_to_db(power: f32) -> f32 {
+    if power <= 0.0 {
+        -DB_RANGE
+    } else {
+        (10.0 * power.log10()).max(-DB_RANGE)
+    }
+}
+
+impl Panel for Spectrum {
+    fn name(&self) -> &'static str {
+        "SPECTRUM"
+    }
+
+    fn set_samplerate(&mut self, samplerate: usize) {
+        self.samplerate = samplerate;
+    }
+
+    fn consume_buf(&mut self, buf: &[f32]) {
+        for pair in buf.chunks_exact(2) {
+            self.samples.push_back((pair[0] + pair[1]) / 2.0);
+        }
+        if self.samples.len() > self.fft_size {
+            self.samples.drain(0..self.samples.len() - self.fft_size);
+        }
+        self.fresh = true;
+    }
+
+    fn update(&mut self, _levels: &Levels, dt: f32) {
+        if self.fresh && self.samples.len() == self.fft_size {
+            self.analyze(dt);
+        }
+        self.fresh = false;
+    }
+
+    fn key(&mut self, key_code: KeyCode) {
+        match key_code {
+            KeyCode::KeyB => {
+                self.mode = match self.mode {
+                    Mode::Bands => Mode::Curve,
+                    Mode::Curve => Mode::Bands,
+                }
+            }
+            KeyCode::KeyW => {
+                self.window_function = self.window_function.next();
+                self.window = self.window_function.coefficients(self.fft_size);
+            }
+            KeyCode::KeyF => {
+                let idx = FFT_SIZES.iter().position(|size| *size == self.fft_size);
+                self.fft_size = FFT_SIZES[idx.map_or(0, |idx| (idx + 1) % FFT_SIZES.len())];
+                self.resize();
+            }
+            KeyCode::KeyA => {
+                let idx = AVERAGING_STEPS
+                    .iter()
+                    .position(|avg| *avg == self.averaging);
+                self.averaging =
+                    AVERAGING_STEPS[idx.map_or(0, |idx| (idx + 1) % AVERAGING_STEPS.len())];
+            }
+            KeyCode::KeyP => self.show_peaks = !self.show_peaks,
+            _ => {}
+        }
+    }
+
+    fn draw(
+        &self,
+        canvas: &mut Canvas<OpenGl>,
+        font_ids: &[FontId],
+        x: f32,
+        y: f32,
+        w: f32,
+        h: f32,
+    ) {
+        let plot_x = x + 24.0;
+        let plot_w = w - 28.0;
+        let plot_y = y + 14.0;
+        let plot_h = h - 24.0;
+        let max_freq = MAX_FREQ.min(self.samplerate as f32 / 2.0);
+        let freq_x =
+            |freq: f32| plot_x + plot_w * (freq / MIN_FREQ).log10() / (max_freq / MIN_FREQ).log10();
+        let db_y = |db: f32| plot_y + plot_h * (-db / DB_RANGE).clamp(0.0, 1.0);
+
+        // Grid
+        let mut grid_paint = Paint::color(Color::rgb(52, 46, 46));
+        grid_paint.set_line_width(1.0);
+        let mut label_paint = Paint::color(Color::rgb(80, 72, 72));
+        label_paint.set_font(&[font_ids[0]]);
+        label_paint.set_font_size(8.0);
+
+        label_paint.set_text_align(Align::Right);
+        for db in (0..=DB_RANGE as usize).step_by(30) {
+            let gy = db_y(-(db as f32));
+            let mut path = Path::new();
+            path.move_to(plot_x, gy);
+            path.line_to(plot_x + plot_w, gy);
+            canvas.stroke_path(&path, &grid_paint);
+            canvas
+                .fill_text(plot_x - 4.0, gy + 3.0, format!("-{db}"), &label_paint)
+                .unwrap();
+        }
+
+        label_paint.set_text_align(Align::Center);
+        for (freq, label) in [
+            (20.0, "20"),
+            (50.0, "50"),
+            (100.0, "100"),
+            (200.0, "200"),
+            (500.0, "500"),
+            (1000.0, "1k"),
+            (2000.0, "2k"),
+            (5000.0, "5k"),
+            (10000.0, "10k"),
+            (20000.0, "20k"),
+        ] {
+            if freq > max_freq {
+                continue;
+            }
+            let gx = freq_x(freq);
+            let mut path = Path::new();
+            path.move_to(gx, plot_y);
+            path.line_to(gx, plot_y + plot_h);
+            canvas.stroke_path(&path, &grid_paint);
+            canvas
+                .fill_text(gx, plot_y + plot_h + 9.0, label, &label_paint)
+                .unwrap();
+        }
+
+        label_paint.set_text_align(Align::Left);
+        canvas
+            .fill_text(
+                x + 4.0,
+                y + 10.0,
+                format!(
+                    "{} {} AVG {:.1}s",
+                    self.fft_size,
+                    self.window_function.name(),
+                    self.averaging
+                ),
+                &label_paint,
+            )
+            .unwrap();
+
+        let peak_power: Vec<f32> = self
+            .peak_db
+            .iter()
+            .map(|db| 10.0f32.powf(db / 10.0))
+            .collect();
+
+        canvas.save();
+        canvas.intersect_scissor(plot_x, plot_y, plot_w, plot_h);
+        match self.mode {
+            Mode::Bands => {
+                let band_w = plot_w / 31.0;
+                let mut path = Path::new();
+                for (center, power) in self.bands(&self.power) {
+                    let top = db_y(power_to_db(power));
+                    path.rect(
+                        freq_x(center) - band_w * 0.4,
+                        top,
+                        band_w * 0.8,
+                        plot_y + plot_h - top,
+                    );
+                }
+                canvas.fill_path(&path, &Paint::color(Color::rgba(255, 48, 0, 100)));
+
+                if self.show_peaks {
+                    let mut path = Path::new();
+                    for (center, power) in self.bands(&peak_power) {
+                        let top = db_y(power_to_db(power));
+                        path.move_to(freq_x(center) - band_w * 0.4, top);
+                        path.line_to(freq_x(center) + band_w * 0.4, top);
+                    }
+                    let mut paint = Paint::color(Color::rgb(255, 200, 160));
+                    paint.set_line_width(1.0);
+                    canvas.stroke_path(&path, &paint);
+                }
+            }
+            Mode::Curve => {
+                let mut curves = vec![(&self.power, Color::rgb(255, 200, 160))];
+                if self.show_peaks {
+                    curves.push((&peak_power, Color::rgba(255, 48, 0, 140)));
+                }
+                for (values, color) in curves {
+                    let mut path = Path::new();
+                    let mut started = false;
+                    for (bin, power) in values.iter().enumerate().skip(1) {
+                        let freq = self.bin_freq(bin);
+                        if freq < MIN_FREQ {
+                            continue;
+                        }
+                        let (px, py) = (freq_x(freq), db_y(power_to_db(*power)));
+                        if started {
+                            path.line_to(px, py);
+                        } else {
+                            path.move_to(px, py);
+                            started = true;
+                        }
+                    }
+                    let mut paint = Paint::color(color);
+                    paint.set_line_width(1.0);
+                    canvas.stroke_path(&path, &paint);
+                }
+            }
+        }
+        canvas.restore();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use super::*;
+
+    const SAMPLERATE: usize = 48000;
+
+    /// Band level in dB of a sine on both channels, after one analysis
+    fn band_db(window_function: WindowFunction, frequency: f32, amplitude: f32) -> f32 {
+        let mut spectrum = Spectrum::new(4096, window_function, 0.0, 1.0);
+        spectrum.set_samplerate(SAMPLERATE);
+        let buf: Vec<f32> = (0..4096)
+            .flat_map(|n| {
+                let sample = amplitude * (TAU * frequency * n as f32 / SAMPLERATE as f32).sin();
+                [sample; 2]
+            })
+            .collect();
+        spectrum.consume_buf(&buf);
+        spectrum.analyze(0.01);
+
+        let bands = spectrum.bands(&spectrum.power);
+        let (_, power) = bands
+            .iter()
+            .min_by(|a, b| (a.0 - frequency).abs().total_cmp(&(b.0 - frequency).abs()))
+            .unwrap();
+        power_to_db(*power)
+    }
+
+    #[test]
+    fn full_scale_sine_reads_0db_in_its_band_whatever_the_window() {
+        let windows = [
+            WindowFunction::Rectangular,
+            WindowFunction::Hann,
+            WindowFunction::BlackmanHarris,
+            WindowFunction::FlatTop,
+        ];
+        for window_function in windows {
+            // Between bins, so the power spreads over several of them
+            for frequency in [1000.0, 5000.0] {
+                let db = band_db(window_function, frequency, 1.0);
+                assert!(
+                    db.abs() < 0.2,
+                    "{} {frequency}Hz reads {db:.2}dB",
+                    window_function.name()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn band_level_follows_the_sine_level() {
+        let db = band_db(WindowFunction::Hann, 1000.0, 0.1);
+        assert!((db + 20.0).abs() < 0.2, "reads {db:.2}dB");
+    }
+}
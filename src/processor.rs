@@ -1,86 +1,419 @@
-use std::{collections::VecDeque, ops::Mul};
+use std::{collections::VecDeque, f32::consts::SQRT_2};
 
+use biquad::DirectForm2Transposed;
 use instant::Instant;
 
-use crate::db_to_multiplier;
+use crate::{
+    ballistics::{Ballistics, MotionFilter},
+    clip_log::ClipLog,
+    config::Config,
+    db_to_multiplier,
+    integrator::{Integration, Integrator},
+    needle::Movement,
+    overload::{OverloadDetector, OverloadTrigger},
+    scales::Deflection,
+    weighting::{run_filters, Weighting},
+};
+
+/// Peak fall-back rate, a Type I PPM return (20dB in 1.7s)
+const PEAK_FALLBACK_DB_PER_SEC: f32 = 20.0 / 1.7;
+/// Quasi-peak attack time constant, so 10, 5 and 3ms bursts read 1, 2 and 4dB
+/// low on the PPM needle like an IEC 60268-10 Type I PPM
+const QUASI_PEAK_ATTACK_SECONDS: f32 = 0.0014;
+
+/// Needle ballistics run at this rate in Hz, whatever the audio or display rate
+pub const CONTROL_RATE: usize = 1000;
 
 pub struct Processor {
-    squares: VecDeque<[i64; 2]>,
-    square_sums: VecDeque<[i64; 2]>,
+    integrators: [Integrator; 2],
+    /// Mean squares after each sample pair of the latest buffer
+    mean_squares: VecDeque<[f64; 2]>,
     head_instant: Instant,
     samplerate: usize,
     pub preamp: f32,
+    /// Peak, max and clips measure the input, before the preamp
+    peaks: [f32; 2],
+    /// Type I quasi-peaks driving a PPM needle, preamp applied
+    quasi_peaks: [f32; 2],
+    max_peaks: [f32; 2],
+    clips: [u64; 2],
+    clipping: [bool; 2],
+    overload_detectors: [OverloadDetector; 2],
+    /// Whether each channel overloaded since the last take_overloads
+    overloads: [bool; 2],
+    clip_log: ClipLog,
+    /// Frames into the input, for inputs where that is a position
+    position: Option<u64>,
+    weighting: [Weighting; 2],
+    weighting_filters: [Vec<DirectForm2Transposed<f64>>; 2],
+    pub deflection: Deflection,
+    ballistics: [Ballistics; 2],
+    motion_filters: [Box<dyn MotionFilter>; 2],
+    /// Advances by CONTROL_RATE per sample, a needle tick is due each time it
+    /// reaches the sample rate
+    control_phase: usize,
+    /// Needle positions at control rate for the latest buffer
+    needles: VecDeque<[f32; 2]>,
 }
 
 impl Processor {
     pub fn new() -> Self {
         Self {
-            squares: VecDeque::new(),
-            square_sums: VecDeque::new(),
+            integrators: std::array::from_fn(|_| {
+                Integrator::new(Integration::Rectangular(0.02), 44100)
+            }),
+            mean_squares: VecDeque::new(),
             head_instant: Instant::now(),
             samplerate: 44100,
             preamp: db_to_multiplier(18.0),
+            peaks: [0.0; 2],
+            quasi_peaks: [0.0; 2],
+            max_peaks: [0.0; 2],
+            clips: [0; 2],
+            clipping: [false; 2],
+            overload_detectors: std::array::from_fn(|_| {
+                OverloadDetector::new(OverloadTrigger::Rms, 1.0)
+            }),
+            overloads: [false; 2],
+            clip_log: ClipLog::default(),
+            position: None,
+            weighting: [Weighting::Z; 2],
+            weighting_filters: Default::default(),
+            deflection: Deflection {
+                negative_db_range: 53.4,
+                positive_db_range: 6.0,
+                bend: 2.0,
+            },
+            ballistics: [Ballistics::Mechanical(Movement::SifamVu); 2],
+            motion_filters: std::array::from_fn(|_| {
+                Ballistics::Mechanical(Movement::SifamVu).filter(CONTROL_RATE as f32)
+            }),
+            control_phase: 0,
+            needles: VecDeque::new(),
         }
     }
 
     pub fn set_samplerate(&mut self, samplerate: usize) {
         self.samplerate = samplerate;
+        self.control_phase = 0;
+        for channel in 0..2 {
+            self.set_weighting(channel, self.weighting[channel]);
+            self.integrators[channel].set_samplerate(samplerate);
+        }
+    }
+
+    pub fn integration(&self, channel: usize) -> Integration {
+        self.integrators[channel].integration()
+    }
+
+    pub fn set_integration(&mut self, channel: usize, integration: Integration) {
+        self.integrators[channel].set_integration(integration);
+    }
+
+    pub fn ballistics(&self, channel: usize) -> Ballistics {
+        self.ballistics[channel]
+    }
+
+    /// Swaps the needle motion filter, the needle carrying on from where it is
+    pub fn set_ballistics(&mut self, channel: usize, ballistics: Ballistics) {
+        self.ballistics[channel] = ballistics;
+        let mut filter = ballistics.filter(CONTROL_RATE as f32);
+        if let Some(needles) = self.needles.back() {
+            filter.settle(needles[channel]);
+        }
+        self.motion_filters[channel] = filter;
+    }
+
+    /// Applies the weighting, integration, ballistics and overload settings
+    pub fn configure(&mut self, config: &Config) {
+        for channel in 0..2 {
+            self.set_weighting(channel, config.weighting[channel]);
+            self.set_integration(channel, config.integration[channel]);
+            self.set_ballistics(channel, config.ballistics[channel]);
+        }
+        self.set_overload(config.overload_trigger, config.overload_threshold);
+    }
+
+    pub fn set_overload(&mut self, trigger: OverloadTrigger, threshold_db: f32) {
+        self.overload_detectors =
+            std::array::from_fn(|_| OverloadDetector::new(trigger, db_to_multiplier(threshold_db)));
+    }
+
+    pub fn weighting(&self, channel: usize) -> Weighting {
+        self.weighting[channel]
+    }
+
+    pub fn set_weighting(&mut self, channel: usize, weighting: Weighting) {
+        self.weighting[channel] = weighting;
+        self.weighting_filters[channel] = weighting.filters(self.samplerate);
     }
 
     pub fn consume_buf(&mut self, buf: Vec<f32>) {
-        // 300ms window
         self.head_instant = Instant::now();
-        let window_len = (self.samplerate as f32 * 0.3) as usize;
 
-        if !self.square_sums.is_empty() {
-            // Leave only last square_sum
-            self.square_sums.drain(0..self.square_sums.len() - 1);
+        if buf.len() >= 2 {
+            // Only the latest buffer is replayed by get_hands_for_instant
+            self.mean_squares.clear();
         }
 
-        if self.squares.len() > window_len {
-            let excess = window_len - self.squares.len();
-            // Leave only window requred for managing square_sums
-            self.squares.drain(0..excess);
-        }
+        let peak_fallback = db_to_multiplier(-PEAK_FALLBACK_DB_PER_SEC / self.samplerate as f32);
+        let quasi_peak_attack =
+            1.0 - (-1.0 / (QUASI_PEAK_ATTACK_SECONDS * self.samplerate as f32)).exp();
+        let mut ticks = 0;
+
+        for incoming_pair in buf.chunks_exact(2) {
+            let incoming_pair = [0, 1].map(|ch| {
+                run_filters(&mut self.weighting_filters[ch], incoming_pair[ch] as f64) as f32
+            });
 
-        for incoming_pair in buf.chunks(2) {
-            let rms_head_squares = if self.squares.len() >= window_len {
-                self.squares.pop_front().unwrap()
-            } else {
-                [0; 2]
-            };
+            for (ch, sample) in incoming_pair.iter().enumerate() {
+                let level = sample.abs();
+                self.peaks[ch] = level.max(self.peaks[ch] * peak_fallback);
+                let quasi_peak = &mut self.quasi_peaks[ch];
+                *quasi_peak *= peak_fallback;
+                if level * self.preamp > *quasi_peak {
+                    *quasi_peak += (level * self.preamp - *quasi_peak) * quasi_peak_attack;
+                }
+                self.max_peaks[ch] = self.max_peaks[ch].max(level);
 
-            let square_sums = self.square_sums.back().unwrap_or(&[0; 2]);
+                // Count overs, not clipped samples
+                let clipping = level >= 1.0;
+                if clipping && !self.clipping[ch] {
+                    self.clips[ch] += 1;
+                }
+                self.clipping[ch] = clipping;
+            }
 
-            let incoming_squares = [
-                (incoming_pair[0].clamp(-1.0, 1.0).mul(16384.0) as i64).pow(2),
-                (incoming_pair[1].clamp(-1.0, 1.0).mul(16384.0) as i64).pow(2),
-            ];
+            let [l, r] = incoming_pair;
+            let mean_squares = [self.integrators[0].push(l), self.integrators[1].push(r)];
+            self.mean_squares.push_back(mean_squares);
 
-            self.squares.push_back(incoming_squares);
+            for ch in 0..2 {
+                let sample = incoming_pair[ch];
+                let overloaded = self.overload_detectors[ch].push(sample, mean_squares[ch]);
+                self.overloads[ch] |= overloaded;
+                self.clip_log
+                    .update(ch, overloaded, sample.abs(), self.position, self.samplerate);
+            }
+            if let Some(position) = &mut self.position {
+                *position += 1;
+            }
 
-            self.square_sums.push_back([
-                square_sums[0] + incoming_squares[0] - rms_head_squares[0],
-                square_sums[1] + incoming_squares[1] - rms_head_squares[1],
-            ]);
+            // Rates CONTROL_RATE doesn't divide, like 44.1kHz, still tick at exactly
+            // CONTROL_RATE on average
+            self.control_phase += CONTROL_RATE;
+            while self.control_phase >= self.samplerate {
+                self.control_phase -= self.samplerate;
+                ticks += 1;
+                let needles = [0, 1].map(|ch| {
+                    let level = match self.ballistics[ch] {
+                        // A PPM movement comes with its quasi-peak driver, aligned
+                        // so a steady sine reads the same as on RMS
+                        Ballistics::Mechanical(Movement::SifamPpm) => {
+                            self.quasi_peaks[ch] / SQRT_2
+                        }
+                        _ => mean_squares[ch].sqrt() as f32 * self.preamp,
+                    };
+                    let drive = self.deflection.position(level);
+                    self.motion_filters[ch].process(drive)
+                });
+                self.needles.push_back(needles);
+            }
         }
 
-        if self.square_sums.len() > buf.len() / 2 {
-            // Leave only buf size square_sums
-            self.square_sums.drain(0..self.square_sums.len() - buf.len() / 2);
+        if self.needles.len() > ticks.max(1) {
+            // Leave only ticks of this buffer for get_needles_for_instant
+            self.needles.drain(0..self.needles.len() - ticks.max(1));
         }
     }
 
+    /// Meters a buffer that isn't part of the input, like silence while it is gone
+    pub fn consume_silence(&mut self, buf: Vec<f32>) {
+        let position = self.position.take();
+        self.consume_buf(buf);
+        self.position = position;
+    }
+
+    /// Counts clip positions from the start of a newly opened input, if `positioned`
+    pub fn start_input(&mut self, positioned: bool) {
+        self.position = positioned.then_some(0);
+    }
+
+    /// Needle deflections in [0.0, 1.0] after ballistics, replaying the latest buffer in real time
+    pub fn get_needles_for_instant(&self, instant: Instant) -> [f32; 2] {
+        let offset = (instant.duration_since(self.head_instant).as_secs_f32() * CONTROL_RATE as f32)
+            as usize;
+        *self
+            .needles
+            .get(offset)
+            .or(self.needles.back())
+            .unwrap_or(&[0.0; 2])
+    }
+
+    /// RMS levels replaying the latest buffer in real time, preamp applied
     pub fn get_hands_for_instant(&self, instant: Instant) -> [f32; 2] {
-        let window_len = (self.samplerate as f32 * 0.3) as i64;
-
-        let offset = (instant.duration_since(self.head_instant).as_secs_f32() * (self.samplerate as f32)) as usize;
-        let square_sums = self.square_sums.get(offset).unwrap_or(self.square_sums.back().unwrap_or(&[0; 2]));
-        let sqrt = ((square_sums[0] / window_len) as f32).sqrt() / 16384.0;
-        let sqrt_2 = ((square_sums[1] / window_len) as f32).sqrt() / 16384.0;
-        [
-            if sqrt.is_nan() { 0.0 } else { sqrt } * self.preamp,
-            if sqrt_2.is_nan() { 0.0 } else { sqrt } * self.preamp,
-        ]
-    }
-}
\ No newline at end of file
+        let offset = (instant.duration_since(self.head_instant).as_secs_f32()
+            * (self.samplerate as f32)) as usize;
+        let mean_squares = self
+            .mean_squares
+            .get(offset)
+            .or(self.mean_squares.back())
+            .unwrap_or(&[0.0; 2]);
+        mean_squares.map(|mean_square| mean_square.max(0.0).sqrt() as f32 * self.preamp)
+    }
+
+    /// Sample peaks with PPM-like fall-back, relative to full scale without the preamp
+    pub fn get_peaks(&self) -> [f32; 2] {
+        self.peaks
+    }
+
+    /// Highest sample peaks since the last reset, relative to full scale without the preamp
+    pub fn get_max_peaks(&self) -> [f32; 2] {
+        self.max_peaks
+    }
+
+    /// Number of overs (runs of input samples at or above full scale) since the last reset
+    pub fn get_clips(&self) -> [u64; 2] {
+        self.clips
+    }
+
+    /// Whether each channel overloaded since the previous call
+    pub fn take_overloads(&mut self) -> [bool; 2] {
+        std::mem::take(&mut self.overloads)
+    }
+
+    pub fn clip_log(&self) -> &ClipLog {
+        &self.clip_log
+    }
+
+    pub fn reset_stats(&mut self, channel: usize) {
+        self.max_peaks[channel] = 0.0;
+        self.clips[channel] = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+    use crate::multiplier_to_db;
+
+    #[test]
+    fn hands_read_quiet_stereo_sine_accurately() {
+        let samplerate = 48000;
+        let mut processor = Processor::new();
+        processor.set_samplerate(samplerate);
+        processor.preamp = 1.0;
+
+        // 3s of a -120dBFS sine in 10ms buffers, right channel 20dB louder
+        let amplitude = db_to_multiplier(-120.0) as f64;
+        for buf_idx in 0..300 {
+            let buf = (0..480)
+                .flat_map(|n| {
+                    let t = (buf_idx * 480 + n) as f64 / samplerate as f64;
+                    let s = amplitude * (2.0 * PI * 997.0 * t).sin();
+                    [s as f32, (s * 10.0) as f32]
+                })
+                .collect();
+            processor.consume_buf(buf);
+        }
+
+        let hands = processor.get_hands_for_instant(Instant::now());
+        let sine_rms_offset = 10.0 * 2.0f32.log10();
+        for (hand, expected) in hands.into_iter().zip([-120.0, -100.0]) {
+            let error = multiplier_to_db(hand) - (expected - sine_rms_offset);
+            assert!(error.abs() < 0.05, "reads {error:.3}dB off");
+        }
+    }
+
+    #[test]
+    fn needles_tick_at_control_rate_at_44_1khz() {
+        let mut processor = Processor::new();
+        processor.set_samplerate(44100);
+        // 10ms buffers leave 10 needle positions each
+        for _ in 0..100 {
+            processor.consume_buf(vec![0.5; 441 * 2]);
+            assert_eq!(processor.needles.len(), 10);
+        }
+    }
+
+    #[test]
+    fn peak_max_and_clips_read_the_input_whatever_the_preamp() {
+        let samplerate = 48000;
+        let mut processor = Processor::new();
+        processor.set_samplerate(samplerate);
+        assert_eq!(multiplier_to_db(processor.preamp), 18.0);
+
+        processor.consume_buf(cosine(samplerate, -12.0));
+
+        for level in [processor.get_peaks(), processor.get_max_peaks()].concat() {
+            assert!((multiplier_to_db(level) + 12.0).abs() < 0.01);
+        }
+        assert_eq!(processor.get_clips(), [0, 0]);
+
+        processor.consume_buf(vec![1.0, 0.5, 1.0, 0.5, 0.0, 0.0, 1.0, 0.5]);
+        assert_eq!(processor.get_clips(), [2, 0]);
+    }
+
+    /// 0.5s of a 1kHz cosine peaking at `peak_db`, so the first sample is the peak
+    fn cosine(samplerate: usize, peak_db: f32) -> Vec<f32> {
+        let amplitude = db_to_multiplier(peak_db) as f64;
+        (0..samplerate / 2)
+            .flat_map(|n| {
+                let s = amplitude * (2.0 * PI * 1000.0 * n as f64 / samplerate as f64).cos();
+                [s as f32; 2]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn overload_thresholds_are_dbfs_at_the_default_preamp() {
+        let rms = Config::default().overload_trigger;
+        for (trigger, threshold_db, peak_db, overloaded) in [
+            (OverloadTrigger::SamplePeak, 0.0, -12.0, false),
+            (OverloadTrigger::SamplePeak, 0.0, 0.0, true),
+            (OverloadTrigger::TruePeak, 0.0, -12.0, false),
+            (OverloadTrigger::Consecutive(2), 0.0, -12.0, false),
+            (OverloadTrigger::Rms, 0.0, -12.0, false),
+            (rms, Config::default().overload_threshold, -12.0, true),
+            (rms, Config::default().overload_threshold, -24.0, false),
+        ] {
+            let mut processor = Processor::new();
+            processor.set_samplerate(48000);
+            processor.set_overload(trigger, threshold_db);
+            processor.consume_buf(cosine(48000, peak_db));
+            assert_eq!(
+                processor.take_overloads(),
+                [overloaded; 2],
+                "{trigger:?} at {threshold_db}dBFS, sine peaking at {peak_db}dBFS"
+            );
+        }
+    }
+
+    #[test]
+    fn clip_positions_count_input_frames_only() {
+        let mut processor = Processor::new();
+        processor.set_samplerate(48000);
+        processor.set_overload(OverloadTrigger::SamplePeak, 0.0);
+        let clip_at = |frame: usize| {
+            let mut buf = vec![0.0; 200];
+            buf[frame * 2] = 1.0;
+            buf
+        };
+        let positions = |processor: &Processor| -> Vec<_> {
+            let events = processor.clip_log().events();
+            events.iter().map(|event| event.position).collect()
+        };
+
+        processor.consume_buf(clip_at(10));
+        processor.start_input(true);
+        processor.consume_buf(clip_at(20));
+        processor.consume_silence(vec![0.0; 200]);
+        processor.consume_buf(clip_at(30));
+        processor.start_input(true);
+        processor.consume_buf(clip_at(40));
+        processor.consume_buf(vec![0.0; 2]);
+        assert_eq!(positions(&processor), [None, Some(20), Some(130), Some(40)]);
+    }
+}
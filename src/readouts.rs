@@ -0,0 +1,69 @@
+use femtovg::{renderer::OpenGl, Align, Canvas, Color, FontId, Paint};
+
+use crate::multiplier_to_db;
+
+const READOUTS_Y: f32 = 182.0;
+// Columns sit left and right of the needle hub, clear of the needle sweep
+const COLUMN_OFFSETS: [f32; 4] = [-110.0, -70.0, 70.0, 110.0];
+const COLUMN_WIDTH: f32 = 40.0;
+
+/// Levels as linear multipliers of full scale, shown and published in dBFS. The
+/// preamp only moves the needles.
+pub struct MeterReadouts {
+    pub rms: f32,
+    pub peak: f32,
+    pub max_peak: f32,
+    pub clips: u64,
+}
+
+pub fn format_db(value: f32) -> String {
+    if value <= 0.0 {
+        "-inf".into()
+    } else {
+        format!("{:.1}", multiplier_to_db(value))
+    }
+}
+
+pub fn draw_readouts(
+    canvas: &mut Canvas<OpenGl>,
+    font_ids: &[FontId],
+    x_base: f32,
+    readouts: &MeterReadouts,
+) {
+    let columns = [
+        ("RMS", format_db(readouts.rms), false),
+        ("PEAK", format_db(readouts.peak), false),
+        ("MAX", format_db(readouts.max_peak), false),
+        ("CLIP", readouts.clips.to_string(), readouts.clips > 0),
+    ];
+
+    for ((label, value, warn), offset) in columns.into_iter().zip(COLUMN_OFFSETS) {
+        let x = x_base + offset;
+
+        let mut paint = Paint::color(Color::rgb(80, 72, 72));
+        paint.set_text_align(Align::Center);
+        paint.set_font(&[font_ids[0]]);
+        paint.set_font_size(8.0);
+        canvas.fill_text(x, READOUTS_Y, label, &paint).unwrap();
+
+        if warn {
+            paint.set_color(Color::rgb(220, 62, 73));
+        }
+        paint.set_font(&[font_ids[1]]);
+        paint.set_font_size(12.0);
+        canvas
+            .fill_text(x, READOUTS_Y + 12.0, value, &paint)
+            .unwrap();
+    }
+}
+
+/// Whether a point in canvas coordinates lands on the readouts of the meter at `x_base`
+pub fn readouts_hit(x_base: f32, point: (f32, f32)) -> bool {
+    let (x, y) = point;
+    if !(READOUTS_Y - 12.0..=READOUTS_Y + 16.0).contains(&y) {
+        return false;
+    }
+    COLUMN_OFFSETS
+        .iter()
+        .any(|offset| (x - (x_base + offset)).abs() <= COLUMN_WIDTH / 2.0)
+}
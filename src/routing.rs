@@ -0,0 +1,285 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail};
+use femtovg::{renderer::OpenGl, Align, Canvas, Color, FontId, Paint, Path};
+
+use crate::{db_to_multiplier, stereo_mode::StereoMode, VU_WIDTH};
+
+const CELL_W: f32 = 28.0;
+const CELL_H: f32 = 18.0;
+const HEADER_W: f32 = 24.0;
+const OVERLAY_Y: f32 = 60.0;
+
+/// One input channel summed into a meter
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Route {
+    channel: usize,
+    gain_db: f32,
+    /// `gain_db` as a multiplier, kept in step so buffers don't convert it
+    gain: f32,
+}
+
+impl Route {
+    fn new(channel: usize, gain_db: f32) -> Self {
+        Self {
+            channel,
+            gain_db,
+            gain: db_to_multiplier(gain_db),
+        }
+    }
+}
+
+/// Maps input channels onto the two meters, each meter being a sum of
+/// input channels with gains in dB
+#[derive(Clone, Debug, PartialEq)]
+pub struct Routing {
+    meters: [Vec<Route>; 2],
+}
+
+impl Default for Routing {
+    fn default() -> Self {
+        Self {
+            meters: [vec![Route::new(0, 0.0)], vec![Route::new(1, 0.0)]],
+        }
+    }
+}
+
+impl Routing {
+    /// Mixes interleaved frames of `channels` inputs down to interleaved meter pairs.
+    /// Routes to channels the device doesn't have contribute silence.
+    pub fn apply(&self, buf: &[f32], channels: usize) -> Vec<f32> {
+        let mut out = Vec::with_capacity(buf.len() / channels.max(1) * 2);
+        for frame in buf.chunks_exact(channels.max(1)) {
+            for routes in &self.meters {
+                out.push(
+                    routes
+                        .iter()
+                        .filter_map(|route| Some(frame.get(route.channel)? * route.gain))
+                        .sum(),
+                );
+            }
+        }
+        out
+    }
+
+    pub fn gain_db(&self, meter: usize, channel: usize) -> Option<f32> {
+        self.meters[meter]
+            .iter()
+            .find(|route| route.channel == channel)
+            .map(|route| route.gain_db)
+    }
+
+    /// Adds the channel to the meter at unity gain, or removes it if already routed
+    pub fn toggle(&mut self, meter: usize, channel: usize) {
+        let routes = &mut self.meters[meter];
+        if let Some(idx) = routes.iter().position(|route| route.channel == channel) {
+            routes.remove(idx);
+        } else {
+            routes.push(Route::new(channel, 0.0));
+            routes.sort_by_key(|route| route.channel);
+        }
+    }
+
+    pub fn adjust_gain(&mut self, meter: usize, channel: usize, delta_db: f32) {
+        if let Some(route) = self.meters[meter]
+            .iter_mut()
+            .find(|route| route.channel == channel)
+        {
+            *route = Route::new(channel, (route.gain_db + delta_db).clamp(-60.0, 24.0));
+        }
+    }
+}
+
+/// Parses `<meter>,<meter>` where each meter is `+`-separated `<channel>[@<gain dB>]`
+/// terms with 1-based channels, e.g. `1@-3+2@-3,3`.
+/// A channel appears at most once per meter.
+impl FromStr for Routing {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let meters: Vec<&str> = s.split(',').collect();
+        let [left, right] = meters[..] else {
+            bail!("routing '{s}' must have exactly two meters separated by ','");
+        };
+
+        let parse_meter = |meter: &str| -> Result<Vec<Route>, anyhow::Error> {
+            let mut routes: Vec<Route> = vec![];
+            for term in meter.split('+') {
+                let (channel, gain_db) = term.split_once('@').unwrap_or((term, "0"));
+                let channel: usize = channel
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow!("invalid channel '{channel}' in routing '{s}'"))?;
+                if channel == 0 {
+                    bail!("channels are numbered from 1 in routing '{s}'");
+                }
+                let gain_db: f32 = gain_db
+                    .trim()
+                    .trim_end_matches("dB")
+                    .parse()
+                    .map_err(|_| anyhow!("invalid gain '{gain_db}' in routing '{s}'"))?;
+                if routes.iter().any(|route| route.channel == channel - 1) {
+                    bail!("channel {channel} is routed twice to a meter in routing '{s}'");
+                }
+                routes.push(Route::new(channel - 1, gain_db));
+            }
+            Ok(routes)
+        };
+
+        Ok(Self {
+            meters: [parse_meter(left)?, parse_meter(right)?],
+        })
+    }
+}
+
+fn overlay_origin(channels: usize) -> (f32, f32) {
+    let w = HEADER_W + CELL_W * channels as f32;
+    (VU_WIDTH - w / 2.0, OVERLAY_Y)
+}
+
+/// Routing matrix cell under a point in canvas coordinates, as (meter, channel)
+pub fn routing_cell_at(channels: usize, point: (f32, f32)) -> Option<(usize, usize)> {
+    let (x, y) = overlay_origin(channels);
+    let col = (point.0 - x - HEADER_W) / CELL_W;
+    let row = (point.1 - y - CELL_H) / CELL_H;
+    if col < 0.0 || row < 0.0 || col >= channels as f32 || row >= 2.0 {
+        return None;
+    }
+    Some((row as usize, col as usize))
+}
+
+/// Rows are the L/R pair the stereo mode decodes, so outside L/R mode the title
+/// names the meters they end up on
+pub fn draw_routing_overlay(
+    canvas: &mut Canvas<OpenGl>,
+    font_ids: &[FontId],
+    routing: &Routing,
+    channels: usize,
+    stereo_mode: StereoMode,
+) {
+    let (x, y) = overlay_origin(channels);
+
+    let mut path = Path::new();
+    path.rounded_rect(
+        x - 8.0,
+        y - 16.0,
+        HEADER_W + CELL_W * channels as f32 + 16.0,
+        CELL_H * 3.0 + 24.0,
+        4.0,
+    );
+    canvas.fill_path(&path, &Paint::color(Color::rgba(20, 18, 18, 230)));
+
+    let mut paint = Paint::color(Color::rgb(220, 220, 220));
+    paint.set_font(&[font_ids[0]]);
+    paint.set_font_size(8.0);
+    paint.set_text_align(Align::Left);
+    let title = match stereo_mode {
+        StereoMode::LeftRight => "ROUTING".to_string(),
+        _ => format!("ROUTING L/R > {}", stereo_mode.labels().join("/")),
+    };
+    canvas
+        .fill_text(
+            x,
+            y - 4.0,
+            format!("{title}  click: toggle  wheel: gain"),
+            &paint,
+        )
+        .unwrap();
+
+    paint.set_text_align(Align::Center);
+    for channel in 0..channels {
+        let cx = x + HEADER_W + CELL_W * (channel as f32 + 0.5);
+        canvas
+            .fill_text(cx, y + CELL_H - 5.0, (channel + 1).to_string(), &paint)
+            .unwrap();
+    }
+
+    for (meter, label) in ["L", "R"].into_iter().enumerate() {
+        let row_y = y + CELL_H * (meter as f32 + 1.0);
+        paint.set_color(Color::rgb(220, 220, 220));
+        canvas
+            .fill_text(x + HEADER_W / 2.0, row_y + CELL_H - 5.0, label, &paint)
+            .unwrap();
+
+        for channel in 0..channels {
+            let cell_x = x + HEADER_W + CELL_W * channel as f32;
+            let gain_db = routing.gain_db(meter, channel);
+
+            let mut path = Path::new();
+            path.rect(cell_x + 1.0, row_y + 1.0, CELL_W - 2.0, CELL_H - 2.0);
+            let fill = if gain_db.is_some() {
+                Color::rgba(255, 48, 0, 100)
+            } else {
+                Color::rgb(52, 46, 46)
+            };
+            canvas.fill_path(&path, &Paint::color(fill));
+
+            if let Some(gain_db) = gain_db {
+                paint.set_color(Color::rgb(255, 200, 160));
+                canvas
+                    .fill_text(
+                        cell_x + CELL_W / 2.0,
+                        row_y + CELL_H - 5.0,
+                        format!("{gain_db:.0}"),
+                        &paint,
+                    )
+                    .unwrap();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_channels_and_gains() {
+        let routing: Routing = "1@-6+2@-6dB,3".parse().unwrap();
+        assert_eq!(routing.gain_db(0, 0), Some(-6.0));
+        assert_eq!(routing.gain_db(0, 1), Some(-6.0));
+        assert_eq!(routing.gain_db(1, 2), Some(0.0));
+        assert_eq!(routing.gain_db(1, 0), None);
+        assert_eq!("1,2".parse::<Routing>().unwrap(), Routing::default());
+    }
+
+    #[test]
+    fn rejects_malformed_routings() {
+        for routing in ["1", "1,2,3", "0,1", "x,1", "1@loud,2", "1+1@-6,2", ""] {
+            assert!(routing.parse::<Routing>().is_err(), "accepted '{routing}'");
+        }
+        // The same channel may still feed both meters
+        assert!("1,1".parse::<Routing>().is_ok());
+    }
+
+    #[test]
+    fn mixes_frames_down_to_meter_pairs() {
+        let routing: Routing = "1+2@-6.0206,3".parse().unwrap();
+        let buf = [0.5, 1.0, 0.25, -0.5, 0.0, 1.0];
+        let out = routing.apply(&buf, 3);
+        let expected = [1.0, 0.25, -0.5, 1.0];
+        for (out, expected) in out.iter().zip(expected) {
+            assert!((out - expected).abs() < 1e-4, "{out} != {expected}");
+        }
+        assert_eq!(out.len(), 4);
+    }
+
+    #[test]
+    fn missing_channels_are_silent() {
+        let routing: Routing = "1+3,4".parse().unwrap();
+        assert_eq!(
+            routing.apply(&[0.5, 0.25, -0.5, 1.0], 2),
+            [0.5, 0.0, -0.5, 0.0]
+        );
+    }
+
+    #[test]
+    fn gain_changes_apply_to_the_mix() {
+        let mut routing = Routing::default();
+        routing.adjust_gain(0, 0, -6.0206);
+        routing.toggle(1, 0);
+        let out = routing.apply(&[1.0, 1.0], 2);
+        assert!((out[0] - 0.5).abs() < 1e-4);
+        assert!((out[1] - 2.0).abs() < 1e-4);
+    }
+}
@@ -4,6 +4,23 @@ use femtovg::{renderer::OpenGl, Canvas, Color, FontId, Paint, Path, Solidity};
 
 use crate::{db_to_normalized, normalized_to_db};
 
+/// Maps linear levels onto needle deflection in [0.0, 1.0]
+#[derive(Clone, Copy, Debug)]
+pub struct Deflection {
+    pub negative_db_range: f32,
+    pub positive_db_range: f32,
+    pub bend: f32,
+}
+
+impl Deflection {
+    pub fn position(&self, value: f32) -> f32 {
+        let db = normalized_to_db(value, self.negative_db_range);
+        let db = (db + self.negative_db_range).max(0.0);
+        let position = db / (self.negative_db_range + self.positive_db_range);
+        position.powf(self.bend)
+    }
+}
+
 pub struct Mark {
     position: f32,
     label: Option<String>,
@@ -0,0 +1,107 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+/// How the two input channels are matrixed onto the two meters
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StereoMode {
+    LeftRight,
+    /// M = (L + R) / 2, S = (L - R) / 2, so mono material reads the same on M as on L
+    MidSide,
+    /// Unscaled L + R and L - R
+    SumDifference,
+}
+
+impl StereoMode {
+    pub fn labels(&self) -> [&'static str; 2] {
+        match self {
+            StereoMode::LeftRight => ["L", "R"],
+            StereoMode::MidSide => ["M", "S"],
+            StereoMode::SumDifference => ["L+R", "L-R"],
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            StereoMode::LeftRight => StereoMode::MidSide,
+            StereoMode::MidSide => StereoMode::SumDifference,
+            StereoMode::SumDifference => StereoMode::LeftRight,
+        }
+    }
+
+    /// Decodes interleaved stereo pairs in place
+    pub fn decode(&self, buf: &mut [f32]) {
+        let scale = match self {
+            StereoMode::LeftRight => return,
+            StereoMode::MidSide => 0.5,
+            StereoMode::SumDifference => 1.0,
+        };
+        for pair in buf.chunks_exact_mut(2) {
+            let (l, r) = (pair[0], pair[1]);
+            pair[0] = (l + r) * scale;
+            pair[1] = (l - r) * scale;
+        }
+    }
+}
+
+impl FromStr for StereoMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lr" => Ok(StereoMode::LeftRight),
+            "ms" => Ok(StereoMode::MidSide),
+            "sum-diff" => Ok(StereoMode::SumDifference),
+            _ => Err(anyhow!("unknown stereo mode '{s}'")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoded(mode: StereoMode, buf: &[f32]) -> Vec<f32> {
+        let mut buf = buf.to_vec();
+        mode.decode(&mut buf);
+        buf
+    }
+
+    #[test]
+    fn left_right_passes_through() {
+        let buf = [0.5, -0.25, 1.0, 0.0];
+        assert_eq!(decoded(StereoMode::LeftRight, &buf), buf);
+    }
+
+    #[test]
+    fn mid_side_reads_mono_on_mid_only() {
+        // Mono in: M reads as L does, S is silent
+        assert_eq!(decoded(StereoMode::MidSide, &[0.5, 0.5]), [0.5, 0.0]);
+        // L only: M and S read the same, half of L
+        assert_eq!(decoded(StereoMode::MidSide, &[0.5, 0.0]), [0.25, 0.25]);
+        // Out of phase: all on S
+        assert_eq!(decoded(StereoMode::MidSide, &[0.5, -0.5]), [0.0, 0.5]);
+    }
+
+    #[test]
+    fn sum_difference_is_unscaled() {
+        let buf = [0.5, 0.25, 0.5, -0.5];
+        assert_eq!(
+            decoded(StereoMode::SumDifference, &buf),
+            [0.75, 0.25, 0.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn parses_mode_names() {
+        assert_eq!("lr".parse::<StereoMode>().unwrap(), StereoMode::LeftRight);
+        assert_eq!("ms".parse::<StereoMode>().unwrap(), StereoMode::MidSide);
+        assert_eq!(
+            "sum-diff".parse::<StereoMode>().unwrap(),
+            StereoMode::SumDifference
+        );
+        for mode in ["", "LR", "m/s", "sum"] {
+            assert!(mode.parse::<StereoMode>().is_err(), "accepted '{mode}'");
+        }
+    }
+}
@@ -0,0 +1,597 @@
+use std::{
+    fmt::Write as _,
+    io::{Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::bail;
+use log::info;
+
+use crate::{
+    clip_log::{unix_seconds, ClipEvent, ClipLog},
+    loudness::Loudness,
+    multiplier_to_db,
+    readouts::MeterReadouts,
+};
+
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const MAX_REQUEST_LEN: usize = 8192;
+/// A client that doesn't finish its request in time is hung up on
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// A dashboard that stops reading is dropped rather than holding up the others
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Connections served at once, WebSocket clients included, further ones are refused
+const MAX_CONNECTIONS: usize = 64;
+/// Clip events included in the `/metrics` snapshot
+const SNAPSHOT_EVENTS: usize = 10;
+
+#[derive(Clone, Debug)]
+pub struct TelemetryConfig {
+    /// Address the HTTP and WebSocket server listens on, off if unset
+    pub listen: Option<SocketAddr>,
+    /// WebSocket updates per second
+    pub rate: f32,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            listen: None,
+            rate: 20.0,
+        }
+    }
+}
+
+/// Written to by the broadcaster and by the client's own thread answering pings
+type Client = Arc<Mutex<TcpStream>>;
+
+enum Broadcast {
+    Client(Client),
+    Frame(String),
+}
+
+/// Counts a connection as open while held
+struct Connection(Arc<AtomicUsize>);
+
+impl Connection {
+    fn open(count: &Arc<AtomicUsize>) -> Option<Self> {
+        let open = count.fetch_add(1, Ordering::Relaxed);
+        // Refused too, so the count goes back down as it's dropped
+        let connection = Connection(count.clone());
+        (open < MAX_CONNECTIONS).then_some(connection)
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Embedded server streaming levels, loudness and clip events as JSON over WebSocket, with
+/// the latest levels as a snapshot on `/metrics`
+pub struct Telemetry {
+    broadcast: Sender<Broadcast>,
+    snapshot: Arc<Mutex<String>>,
+    interval: Duration,
+    last_sent: Instant,
+    /// Clip events recorded when the last update went out
+    events_sent: u64,
+}
+
+impl Telemetry {
+    /// Starts serving, None if telemetry isn't configured
+    pub fn start(config: &TelemetryConfig) -> Result<Option<Self>, anyhow::Error> {
+        let Some(listen) = config.listen else {
+            return Ok(None);
+        };
+        let listener = TcpListener::bind(listen)?;
+        info!("telemetry on http://{listen}/metrics and ws://{listen}/");
+
+        let snapshot = Arc::new(Mutex::new(String::from("{}")));
+        let (broadcast, broadcast_rx) = mpsc::channel();
+        std::thread::spawn(move || broadcaster(broadcast_rx));
+        {
+            let (snapshot, broadcast) = (snapshot.clone(), broadcast.clone());
+            let connections = Arc::new(AtomicUsize::new(0));
+            std::thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let Some(connection) = Connection::open(&connections) else {
+                        // Dropping the stream hangs up
+                        continue;
+                    };
+                    let (snapshot, broadcast) = (snapshot.clone(), broadcast.clone());
+                    std::thread::spawn(move || {
+                        if let Err(err) = serve(stream, &snapshot, &broadcast) {
+                            eprintln!("telemetry request failed: {err}");
+                        }
+                        drop(connection);
+                    });
+                }
+            });
+        }
+
+        Ok(Some(Self {
+            broadcast,
+            snapshot,
+            interval: Duration::from_secs_f32(1.0 / config.rate),
+            last_sent: Instant::now(),
+            events_sent: 0,
+        }))
+    }
+
+    /// Sends levels, loudness and clip events finished since the last update, at the
+    /// configured rate
+    pub fn publish(
+        &mut self,
+        readouts: &[MeterReadouts; 2],
+        loudness: &Loudness,
+        clip_log: &ClipLog,
+    ) {
+        if self.last_sent.elapsed() < self.interval {
+            return;
+        }
+        self.last_sent = Instant::now();
+
+        let events = clip_log.events();
+        let new = (clip_log.recorded() - self.events_sent).min(events.len() as u64) as usize;
+        self.events_sent = clip_log.recorded();
+
+        let frame = to_json(readouts, loudness, events.range(events.len() - new..));
+        let recent = events.range(events.len().saturating_sub(SNAPSHOT_EVENTS)..);
+        *self.snapshot.lock().unwrap() = to_json(readouts, loudness, recent);
+        let _ = self.broadcast.send(Broadcast::Frame(frame));
+    }
+}
+
+/// Levels in dB, null when silent
+fn json_db(level: f32) -> String {
+    if level > 0.0 {
+        format!("{:.2}", multiplier_to_db(level))
+    } else {
+        "null".into()
+    }
+}
+
+/// Loudness in LUFS, null until measured and for silence
+fn json_lufs(lufs: Option<f32>) -> String {
+    match lufs {
+        Some(lufs) if lufs.is_finite() => format!("{lufs:.2}"),
+        _ => "null".into(),
+    }
+}
+
+fn to_json<'a>(
+    readouts: &[MeterReadouts; 2],
+    loudness: &Loudness,
+    events: impl Iterator<Item = &'a ClipEvent>,
+) -> String {
+    let mut json = format!(
+        "{{\"time\":{:.3},\"meters\":[",
+        unix_seconds(SystemTime::now())
+    );
+    for (idx, readouts) in readouts.iter().enumerate() {
+        if idx > 0 {
+            json.push(',');
+        }
+        write!(
+            json,
+            "{{\"rms_db\":{},\"peak_db\":{},\"max_peak_db\":{},\"clips\":{}}}",
+            json_db(readouts.rms),
+            json_db(readouts.peak),
+            json_db(readouts.max_peak),
+            readouts.clips
+        )
+        .unwrap();
+    }
+    write!(
+        json,
+        "],\"loudness\":{{\"momentary_lufs\":{},\"short_term_lufs\":{}}}",
+        json_lufs(loudness.momentary()),
+        json_lufs(loudness.short_term())
+    )
+    .unwrap();
+    json.push_str(",\"clip_events\":[");
+    for (idx, event) in events.enumerate() {
+        if idx > 0 {
+            json.push(',');
+        }
+        write!(
+            json,
+            "{{\"channel\":{},\"time\":{:.3},\"position\":{},\"duration\":{:.4},\"peak_db\":{}}}",
+            event.channel + 1,
+            unix_seconds(event.timestamp),
+            event
+                .position
+                .map_or("null".into(), |position| position.to_string()),
+            event.duration,
+            json_db(event.peak)
+        )
+        .unwrap();
+    }
+    json.push_str("]}");
+    json
+}
+
+/// Owns WebSocket clients and writes every update to each of them
+fn broadcaster(rx: Receiver<Broadcast>) {
+    let mut clients: Vec<Client> = vec![];
+    for message in rx {
+        match message {
+            Broadcast::Client(client) => clients.push(client),
+            Broadcast::Frame(json) => {
+                let frame = text_frame(&json);
+                clients.retain(|client| {
+                    let mut stream = client.lock().unwrap();
+                    let sent = stream.write_all(&frame).is_ok();
+                    if !sent {
+                        // Wakes the client's thread up so the connection is let go
+                        let _ = stream.shutdown(Shutdown::Both);
+                    }
+                    sent
+                });
+            }
+        }
+    }
+}
+
+/// Answers one HTTP request, handing WebSocket upgrades to the broadcaster
+fn serve(
+    mut stream: TcpStream,
+    snapshot: &Mutex<String>,
+    broadcast: &Sender<Broadcast>,
+) -> Result<(), anyhow::Error> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let mut request = vec![];
+    let mut chunk = [0; 1024];
+    while !request.ends_with(b"\r\n\r\n") {
+        let read = stream.read(&mut chunk)?;
+        if read == 0 || request.len() > MAX_REQUEST_LEN {
+            return Ok(());
+        }
+        request.extend(&chunk[..read]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut lines = request.lines();
+    let path = lines
+        .next()
+        .and_then(|line| line.strip_prefix("GET "))
+        .and_then(|line| line.split(' ').next())
+        .unwrap_or_default();
+    let websocket_key = lines.find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.eq_ignore_ascii_case("sec-websocket-key")
+            .then(|| value.trim().to_string())
+    });
+
+    match (path, websocket_key) {
+        ("/", Some(key)) => {
+            write!(
+                stream,
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                 Connection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                websocket_accept(&key)
+            )?;
+            // Dashboards may stay quiet for as long as they like
+            stream.set_read_timeout(None)?;
+            let client = Arc::new(Mutex::new(stream.try_clone()?));
+            let _ = broadcast.send(Broadcast::Client(client.clone()));
+            let result = answer_control_frames(stream, &client);
+            // Lets the broadcaster know to drop it too
+            let _ = client.lock().unwrap().shutdown(Shutdown::Both);
+            result?;
+        }
+        ("/metrics", _) => {
+            let body = snapshot.lock().unwrap().clone();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                 Access-Control-Allow-Origin: *\r\nContent-Length: {}\r\n\
+                 Connection: close\r\n\r\n{body}",
+                body.len()
+            )?;
+        }
+        _ => write!(
+            stream,
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        )?,
+    }
+    Ok(())
+}
+
+/// Reads what a WebSocket client sends until it goes away, answering pings and
+/// closes. Anything else it sends is ignored.
+fn answer_control_frames(mut stream: TcpStream, client: &Client) -> Result<(), anyhow::Error> {
+    loop {
+        let mut header = [0; 2];
+        if stream.read_exact(&mut header).is_err() {
+            // Hung up, or shut down by the broadcaster
+            return Ok(());
+        }
+        let opcode = header[0] & 0x0f;
+        let len = match header[1] & 0x7f {
+            126 => {
+                let mut len = [0; 2];
+                stream.read_exact(&mut len)?;
+                u16::from_be_bytes(len) as u64
+            }
+            127 => {
+                let mut len = [0; 8];
+                stream.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => len as u64,
+        };
+        if len > MAX_REQUEST_LEN as u64 {
+            bail!("WebSocket frame too long, {len} bytes");
+        }
+        // Client frames are always masked
+        let mut mask = [0; 4];
+        if header[1] & 0x80 != 0 {
+            stream.read_exact(&mut mask)?;
+        }
+        let mut payload = vec![0; len as usize];
+        stream.read_exact(&mut payload)?;
+        for (idx, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[idx % 4];
+        }
+
+        match opcode {
+            // Ping, answered with a pong carrying the same data
+            0x9 => client.lock().unwrap().write_all(&frame(0x8a, &payload))?,
+            // Close, echoing the status code before hanging up
+            0x8 => {
+                let status = payload.get(..2).unwrap_or_default();
+                let _ = client.lock().unwrap().write_all(&frame(0x88, status));
+                return Ok(());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Unmasked, unfragmented server text frame
+fn text_frame(text: &str) -> Vec<u8> {
+    frame(0x81, text.as_bytes())
+}
+
+/// Unmasked server frame, `first` holding the FIN bit and opcode
+fn frame(first: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![first];
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend((len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend((len as u64).to_be_bytes());
+        }
+    }
+    frame.extend(payload);
+    frame
+}
+
+fn websocket_accept(key: &str) -> String {
+    base64(&sha1(format!("{key}{WEBSOCKET_GUID}").as_bytes()))
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend((data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            (e, d, c, b, a) = (d, c, b.rotate_left(30), a, temp);
+        }
+        for (h, x) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(x);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(h) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let byte = |i| chunk.get(i).copied().unwrap_or(0);
+        let bits = u32::from_be_bytes([0, byte(0), byte(1), byte(2)]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use super::*;
+
+    #[test]
+    fn websocket_accept_matches_rfc_6455_example() {
+        assert_eq!(
+            websocket_accept("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    fn readouts() -> [MeterReadouts; 2] {
+        [
+            MeterReadouts {
+                rms: 0.5,
+                peak: 1.0,
+                max_peak: 2.0,
+                clips: 3,
+            },
+            MeterReadouts {
+                rms: 0.0,
+                peak: 0.0,
+                max_peak: 0.0,
+                clips: 0,
+            },
+        ]
+    }
+
+    /// Finishes `count` one sample clip events on the second meter
+    fn clip(log: &mut ClipLog, count: u64) {
+        for _ in 0..count {
+            log.update(1, true, 1.0, Some(7), 1000);
+            log.update(1, false, 0.0, Some(8), 1000);
+        }
+    }
+
+    #[test]
+    fn json_has_levels_in_db_and_events() {
+        let mut log = ClipLog::default();
+        clip(&mut log, 1);
+        // Half a second of a full scale 997Hz sine on the left, -3.01LUFS
+        let mut loudness = Loudness::new();
+        loudness.set_samplerate(48000);
+        let sine: Vec<f32> = (0..24000)
+            .flat_map(|n| [(TAU * 997.0 * n as f32 / 48000.0).sin(), 0.0])
+            .collect();
+        loudness.consume_buf(&sine);
+        let json = to_json(&readouts(), &loudness, log.events().iter());
+
+        assert!(json.starts_with("{\"time\":"));
+        assert!(json.contains(
+            "\"meters\":[{\"rms_db\":-6.02,\"peak_db\":0.00,\"max_peak_db\":6.02,\"clips\":3},\
+             {\"rms_db\":null,\"peak_db\":null,\"max_peak_db\":null,\"clips\":0}],\
+             \"loudness\":{\"momentary_lufs\":-3.01,\"short_term_lufs\":null},\
+             \"clip_events\":[{\"channel\":2,\"time\":"
+        ));
+        assert!(json.ends_with(",\"position\":7,\"duration\":0.0010,\"peak_db\":0.00}]}"));
+    }
+
+    #[test]
+    fn each_clip_event_is_sent_once() {
+        let (broadcast, frames) = mpsc::channel();
+        let mut telemetry = Telemetry {
+            broadcast,
+            snapshot: Arc::new(Mutex::new(String::new())),
+            interval: Duration::ZERO,
+            last_sent: Instant::now(),
+            events_sent: 0,
+        };
+        let mut log = ClipLog::default();
+        let sent_events = |telemetry: &mut Telemetry, log: &ClipLog| {
+            telemetry.publish(&readouts(), &Loudness::new(), log);
+            match frames.try_recv() {
+                Ok(Broadcast::Frame(json)) => json.matches("\"channel\"").count(),
+                _ => panic!("no update sent"),
+            }
+        };
+
+        clip(&mut log, 3);
+        assert_eq!(sent_events(&mut telemetry, &log), 3);
+        assert_eq!(sent_events(&mut telemetry, &log), 0);
+        clip(&mut log, 2);
+        assert_eq!(sent_events(&mut telemetry, &log), 2);
+
+        // Events dropped from the log before going out can't be sent
+        clip(&mut log, 20000);
+        assert_eq!(sent_events(&mut telemetry, &log), log.events().len());
+        assert_eq!(telemetry.events_sent, log.recorded());
+        let snapshot = telemetry.snapshot.lock().unwrap().clone();
+        assert_eq!(snapshot.matches("\"channel\"").count(), SNAPSHOT_EVENTS);
+        // Nothing metered yet
+        assert!(snapshot.contains("\"loudness\":{\"momentary_lufs\":null,"));
+    }
+
+    /// Masked client frame, as browsers send them
+    fn client_frame(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [1, 2, 3, 4];
+        let mut frame = vec![first, 0x80 | payload.len() as u8];
+        frame.extend(mask);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(idx, byte)| byte ^ mask[idx % 4]),
+        );
+        frame
+    }
+
+    #[test]
+    fn websocket_clients_get_pongs_and_closes_answered() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let (broadcast, _clients) = mpsc::channel();
+        let server = std::thread::spawn(move || {
+            serve(stream, &Mutex::new(String::new()), &broadcast).unwrap()
+        });
+
+        client
+            .write_all(b"GET / HTTP/1.1\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n")
+            .unwrap();
+        let mut response = vec![];
+        while !response.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            client.read_exact(&mut byte).unwrap();
+            response.extend(byte);
+        }
+        assert!(response.starts_with(b"HTTP/1.1 101 "));
+
+        client.write_all(&client_frame(0x89, b"beat")).unwrap();
+        let mut pong = [0; 6];
+        client.read_exact(&mut pong).unwrap();
+        assert_eq!(pong, *b"\x8a\x04beat");
+
+        client
+            .write_all(&client_frame(0x88, &1000u16.to_be_bytes()))
+            .unwrap();
+        let mut close = vec![];
+        client.read_to_end(&mut close).unwrap();
+        assert_eq!(close, [0x88, 2, 0x03, 0xe8]);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,248 @@
+use std::{f64::consts::PI, str::FromStr};
+
+use anyhow::anyhow;
+use biquad::{Biquad, Coefficients, DirectForm2Transposed};
+
+// IEC 61672 A/C-weighting pole frequencies in Hz
+const POLE_1: f64 = 20.598997;
+const POLE_2: f64 = 107.65265;
+const POLE_3: f64 = 737.86223;
+const POLE_4: f64 = 12194.217;
+
+// ITU-R BS.1770 pre-filter: high shelf, then the RLB high-pass
+const SHELF_GAIN_DB: f64 = 3.99984385;
+const SHELF_FREQ: f64 = 1681.974450955532;
+const SHELF_Q: f64 = 0.7071752369554196;
+const HIGH_PASS_FREQ: f64 = 38.13547087602444;
+const HIGH_PASS_Q: f64 = 0.5003270373238773;
+
+/// Frequency weighting applied ahead of the level detector
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Weighting {
+    Z,
+    A,
+    C,
+    /// ITU-R BS.1770 pre-filter, as used for loudness
+    K,
+}
+
+impl Weighting {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Weighting::Z => "Z",
+            Weighting::A => "A",
+            Weighting::C => "C",
+            Weighting::K => "K",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            Weighting::Z => Weighting::A,
+            Weighting::A => Weighting::C,
+            Weighting::C => Weighting::K,
+            Weighting::K => Weighting::Z,
+        }
+    }
+
+    /// Biquad cascade implementing the weighting at `samplerate`
+    pub fn filters(&self, samplerate: usize) -> Vec<DirectForm2Transposed<f64>> {
+        let fs = samplerate as f64;
+        let sections = match self {
+            Weighting::Z => vec![],
+            Weighting::A => normalize_at_1k(
+                vec![
+                    analog_section(true, POLE_1, POLE_1, fs),
+                    analog_section(true, POLE_2, POLE_3, fs),
+                    analog_section(false, POLE_4, POLE_4, fs),
+                ],
+                fs,
+            ),
+            Weighting::C => normalize_at_1k(
+                vec![
+                    analog_section(true, POLE_1, POLE_1, fs),
+                    analog_section(false, POLE_4, POLE_4, fs),
+                ],
+                fs,
+            ),
+            Weighting::K => k_sections(fs),
+        };
+
+        sections
+            .into_iter()
+            .map(DirectForm2Transposed::<f64>::new)
+            .collect()
+    }
+}
+
+impl FromStr for Weighting {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "z" | "Z" => Ok(Weighting::Z),
+            "a" | "A" => Ok(Weighting::A),
+            "c" | "C" => Ok(Weighting::C),
+            "k" | "K" => Ok(Weighting::K),
+            _ => Err(anyhow!("unknown weighting '{s}'")),
+        }
+    }
+}
+
+/// Runs a sample through a filter cascade
+pub fn run_filters(filters: &mut [DirectForm2Transposed<f64>], sample: f64) -> f64 {
+    filters
+        .iter_mut()
+        .fold(sample, |sample, filter| filter.run(sample))
+}
+
+/// Bilinear transform of `s^2 / ((s + p1)(s + p2))` if `highpass`, else of
+/// `p1 p2 / ((s + p1)(s + p2))`, poles given in Hz and prewarped
+fn analog_section(highpass: bool, pole_1: f64, pole_2: f64, fs: f64) -> Coefficients<f64> {
+    let k = 2.0 * fs;
+    // Keep poles below Nyquist so prewarping stays finite at low sample rates
+    let prewarp = |freq: f64| k * (PI * freq.min(fs * 0.45) / fs).tan();
+    let (a, b) = (prewarp(pole_1), prewarp(pole_2));
+
+    let d0 = (k + a) * (k + b);
+    let d1 = (k + a) * (b - k) + (a - k) * (k + b);
+    let d2 = (a - k) * (b - k);
+
+    let [n0, n1, n2] = if highpass {
+        [k * k, -2.0 * k * k, k * k]
+    } else {
+        [a * b, 2.0 * a * b, a * b]
+    };
+
+    Coefficients {
+        a1: d1 / d0,
+        a2: d2 / d0,
+        b0: n0 / d0,
+        b1: n1 / d0,
+        b2: n2 / d0,
+    }
+}
+
+/// BS.1770 K-weighting at `fs`, giving the coefficients tabled in the standard at
+/// 48kHz. An RBJ cookbook shelf would read some 0.25dB low at 1kHz.
+fn k_sections(fs: f64) -> Vec<Coefficients<f64>> {
+    let shelf = {
+        let k = (PI * SHELF_FREQ / fs).tan();
+        let vh = 10f64.powf(SHELF_GAIN_DB / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / SHELF_Q + k * k;
+        Coefficients {
+            a1: 2.0 * (k * k - 1.0) / a0,
+            a2: (1.0 - k / SHELF_Q + k * k) / a0,
+            b0: (vh + vb * k / SHELF_Q + k * k) / a0,
+            b1: 2.0 * (k * k - vh) / a0,
+            b2: (vh - vb * k / SHELF_Q + k * k) / a0,
+        }
+    };
+    let high_pass = {
+        let k = (PI * HIGH_PASS_FREQ / fs).tan();
+        let a0 = 1.0 + k / HIGH_PASS_Q + k * k;
+        Coefficients {
+            a1: 2.0 * (k * k - 1.0) / a0,
+            a2: (1.0 - k / HIGH_PASS_Q + k * k) / a0,
+            b0: 1.0,
+            b1: -2.0,
+            b2: 1.0,
+        }
+    };
+    vec![shelf, high_pass]
+}
+
+/// Scales the cascade for unity gain at 1kHz
+fn normalize_at_1k(mut sections: Vec<Coefficients<f64>>, fs: f64) -> Vec<Coefficients<f64>> {
+    let omega = 2.0 * PI * 1000.0 / fs;
+    let gain: f64 = sections
+        .iter()
+        .map(|c| {
+            // |H(e^jw)| = |b0 + b1 z^-1 + b2 z^-2| / |1 + a1 z^-1 + a2 z^-2|
+            let magnitude = |c0: f64, c1: f64, c2: f64| {
+                let re = c0 + c1 * omega.cos() + c2 * (2.0 * omega).cos();
+                let im = -c1 * omega.sin() - c2 * (2.0 * omega).sin();
+                (re * re + im * im).sqrt()
+            };
+            magnitude(c.b0, c.b1, c.b2) / magnitude(1.0, c.a1, c.a2)
+        })
+        .product();
+
+    if let Some(first) = sections.first_mut() {
+        first.b0 /= gain;
+        first.b1 /= gain;
+        first.b2 /= gain;
+    }
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLERATES: [usize; 2] = [44100, 48000];
+
+    /// Gain in dB of the weighting for a sine at `frequency`, measured after settling
+    fn gain_db(weighting: Weighting, samplerate: usize, frequency: f64) -> f64 {
+        let mut filters = weighting.filters(samplerate);
+        let (mut input, mut output) = (0.0, 0.0);
+        for n in 0..samplerate {
+            let sample = (2.0 * PI * frequency * n as f64 / samplerate as f64).sin();
+            let filtered = run_filters(&mut filters, sample);
+            if n >= samplerate / 2 {
+                input += sample * sample;
+                output += filtered * filtered;
+            }
+        }
+        10.0 * (output / input).log10()
+    }
+
+    /// Checks the gain at both common sample rates, `tolerance_db` being (above, below)
+    fn assert_gain(
+        weighting: Weighting,
+        frequency: f64,
+        expected_db: f64,
+        tolerance_db: (f64, f64),
+    ) {
+        for samplerate in SAMPLERATES {
+            let gain = gain_db(weighting, samplerate, frequency);
+            assert!(
+                (expected_db - tolerance_db.1..=expected_db + tolerance_db.0).contains(&gain),
+                "{weighting:?} at {frequency}Hz, {samplerate}Hz: {gain:.2}dB, expected {expected_db}dB"
+            );
+        }
+    }
+
+    #[test]
+    fn a_and_c_read_0db_at_1khz() {
+        assert_gain(Weighting::A, 1000.0, 0.0, (0.01, 0.01));
+        assert_gain(Weighting::C, 1000.0, 0.0, (0.01, 0.01));
+    }
+
+    /// IEC 61672-1 design goals, with the class 1 tolerances (+, -) above 4kHz where
+    /// the bilinear transform bends the response
+    #[test]
+    fn a_weighting_follows_iec_61672() {
+        assert_gain(Weighting::A, 100.0, -19.1, (0.2, 0.2));
+        assert_gain(Weighting::A, 4000.0, 1.0, (1.0, 1.0));
+        assert_gain(Weighting::A, 8000.0, -1.1, (1.5, 2.5));
+        assert_gain(Weighting::A, 10000.0, -2.5, (2.0, 3.0));
+    }
+
+    #[test]
+    fn c_weighting_follows_iec_61672() {
+        assert_gain(Weighting::C, 31.5, -3.0, (0.2, 0.2));
+        assert_gain(Weighting::C, 100.0, -0.3, (0.2, 0.2));
+    }
+
+    /// BS.1770 pre-filter: a +4dB shelf above a few kHz, rolled off at the bottom
+    #[test]
+    fn k_weighting_has_a_4db_high_shelf() {
+        assert_gain(Weighting::K, 8000.0, 4.0, (0.1, 0.1));
+        assert_gain(Weighting::K, 16000.0, 4.0, (0.1, 0.1));
+        // Offset by the -0.691 in the loudness formula
+        assert_gain(Weighting::K, 997.0, 0.691, (0.01, 0.01));
+        assert_gain(Weighting::K, 31.5, -7.8, (0.5, 0.5));
+    }
+}